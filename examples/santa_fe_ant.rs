@@ -9,11 +9,13 @@
 #[macro_use]
 extern crate moonlander_gp;
 extern crate rand;
+extern crate rustc_serialize;
 
-use moonlander_gp::{Population, random_population};
-use moonlander_gp::genetic::{SimpleFitness, evolve, Weights, tournament_selection};
+use moonlander_gp::{Population, FitnessCache, HallOfFame, JsonLinesLogger, RunLogger, Parameters, random_population, seeded_rng};
+use moonlander_gp::genetic::{SimpleFitness, evolve, Weights, TreeLimits, LimitPolicy, tournament_selection};
 use moonlander_gp::num::torus;
 use rand::Rng;
+use std::env;
 
 
 const POPULATION_SIZE : usize = 500;
@@ -21,27 +23,50 @@ const MAX_TIME : usize = 600;
 const NR_GENERATIONS : usize = 50;
 const TOURNAMENT_SIZE : usize = 10;
 const MAX_DEPTH : usize = 8;
+const MAX_EVOLVED_DEPTH : usize = 17;
+const MAX_EVOLVED_SIZE : usize = 500;
 
 
 type AntPopulation = Population<Statement, SimpleFitness>;
 
 fn main() {
-    let mut rng = rand::StdRng::new().unwrap();
+    // Pass a seed on the command line to replay an earlier run
+    let seed = env::args().nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| rand::thread_rng().next_u64());
+    println!("Seed {}", seed);
+    let mut rng = seeded_rng(seed);
 
-    let weights = Weights {
-        reproduce: 10,
-        mutate: 20,
-        crossover: 70,
-        tree_height: MAX_DEPTH as i32
-    };
+    // Pass a file name after the seed to log every generation as JSON
+    let mut logger = env::args().nth(2).map(|path| {
+        let mut parameters = Parameters::new();
+        parameters.insert("seed".to_string(), seed.to_string());
+        parameters.insert("population_size".to_string(), POPULATION_SIZE.to_string());
+        JsonLinesLogger::create(path, parameters).unwrap()
+    });
+
+    let weights = Weights::new(10, 20, 70, MAX_DEPTH as i32)
+        .with_limits(TreeLimits::new(MAX_EVOLVED_DEPTH, MAX_EVOLVED_SIZE, LimitPolicy::ReturnParent));
+
+    // The ant trail is deterministic, so there's no need to score the same program twice
+    let mut cache = FitnessCache::new(10 * POPULATION_SIZE);
+
+    let mut hall_of_fame = HallOfFame::new(10);
 
     let mut pop : AntPopulation = random_population(POPULATION_SIZE, MAX_DEPTH, &mut rng);
     for gen in 0..NR_GENERATIONS {
-        pop.score(score_ant, &mut rng);
+        pop.score_cached(score_ant, &mut cache, &mut rng);
+        hall_of_fame.update(&pop);
         println!("Generation {}, best {}, average {}", gen, pop.best_score(), pop.avg_score());
+        if let Some(ref mut logger) = logger {
+            logger.log(&pop).unwrap();
+        }
 
         pop = evolve(pop, &weights, &mut rng, |p, r| tournament_selection(TOURNAMENT_SIZE, p, r));
     }
+
+    let best = hall_of_fame.best().unwrap();
+    println!("Best program found in generation {}, score {}", best.generation, best.score_card.total_score());
 }
 
 fn score_ant(program: &Statement, _: &mut Rng) -> SimpleFitness {
@@ -157,7 +182,7 @@ fn rotate90(d: Direction, right: bool) -> Direction {
 //  AST NODE MACHINERY
 //
 
-#[derive(Clone,Copy)]
+#[derive(Clone,Copy,RustcEncodable)]
 enum Command {
     Left, Right, Move, Skip
 }
@@ -165,7 +190,7 @@ enum Command {
 impl_astnode!(Command, 0,
               int Left(), int Right(), int Move(), int Skip());
 
-#[derive(Clone)]
+#[derive(Clone,RustcEncodable)]
 enum Statement {
     IfFoodAhead(Box<Statement>, Box<Statement>),
     Prog2(Box<Statement>, Box<Statement>),
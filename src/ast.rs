@@ -1,5 +1,7 @@
 use downcast::Any;
 use std::rc::Rc;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
 use rand::Rng;
 
 //----------------------------------------------------------------------
@@ -15,6 +17,14 @@ pub trait AstNode: Any+Mutatable+Copyable+Sync {
 
     /// Return a copy of this node with a single child node replaced.
     fn replace_child(&self, old_child: &AstNode, new_child: &mut Option<Box<AstNode>>) -> Box<AstNode>;
+
+    /// Describe this node, without its children.
+    ///
+    /// Used to compare programs by their structure, so nodes that differ in
+    /// their enum case or data must have different labels. `impl_astnode!()`
+    /// generates a label containing the enum case and its data fields, like
+    /// `Constant(3.5)`.
+    fn label(&self) -> String;
 }
 
 impl_downcast!(AstNode);
@@ -56,6 +66,11 @@ pub fn depth(node: &AstNode) -> usize {
     1 + node.children().into_iter().map(|c| depth(c)).max().unwrap_or(0)
 }
 
+/// Return the number of nodes in an AST tree.
+pub fn size(node: &AstNode) -> usize {
+    1 + node.children().into_iter().map(|c| size(c)).fold(0, |a, b| a + b)
+}
+
 /// A zipper-like structure pointing to a tree node, so a modified copy of the
 /// tree can be reconstructed.
 #[derive(Clone)]
@@ -64,6 +79,64 @@ pub struct NodeInTree<'a> {
     pub root_path: Option<Rc<NodeInTree<'a>>>
 }
 
+/// Format a node label from the name of the node and its data fields.
+///
+/// Used by `impl_astnode!()`.
+pub fn node_label(name: &str, data: Vec<String>) -> String {
+    if data.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}({})", name, data.join(", "))
+    }
+}
+
+/// Return a hash of the structure of an AST tree.
+///
+/// Trees with the same shape and the same node labels have the same hash.
+pub fn structural_hash(node: &AstNode) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_into(node, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_into(node: &AstNode, hasher: &mut DefaultHasher) {
+    let children = node.children();
+    node.node_type().hash(hasher);
+    node.label().hash(hasher);
+    children.len().hash(hasher);
+    for child in children {
+        hash_into(child, hasher);
+    }
+}
+
+/// Return the level of a node in its tree, where the root is at level 1.
+pub fn level(nap: &NodeInTree) -> usize {
+    1 + match nap.root_path {
+        None => 0,
+        Some(ref parent) => level(parent.as_ref())
+    }
+}
+
+/// Return the position of a node in its tree, in pre-order.
+///
+/// This is the index of the node in the list returned by
+/// `find_nodes_and_parents`, where the root is at position 0.
+pub fn position(nap: &NodeInTree) -> usize {
+    match nap.root_path {
+        None => 0,
+        Some(ref parent) => {
+            let mut pos = position(parent.as_ref()) + 1;
+            for child in parent.node.children() {
+                if child as *const AstNode as *const () == nap.node as *const AstNode as *const () {
+                    break;
+                }
+                pos += size(child);
+            }
+            pos
+        }
+    }
+}
+
 /// Return all nodes in a given AST tree.
 pub fn find_nodes_and_parents<'a>(root: &'a AstNode) -> Vec<Rc<NodeInTree<'a>>> {
     let mut result: Vec<Rc<NodeInTree<'a>>> = vec![];
@@ -165,6 +238,14 @@ mod tests {
                                                                 clone_or_replace(y, old_child, new_child)),
             })
         }
+
+        fn label(&self) -> String {
+            match *self {
+                TestNode::Leaf(n) => node_label("Leaf", vec![n.to_string()]),
+                TestNode::Node(n, _) => node_label("Node", vec![n.to_string()]),
+                TestNode::Two(n, _, _) => node_label("Two", vec![n.to_string()]),
+            }
+        }
     }
 
     impl Mutatable for TestNode {
@@ -225,4 +306,25 @@ mod tests {
 
         assert_eq!(2, depth(&tree));
     }
+
+    #[test]
+    fn test_size() {
+        let tree = TestNode::Two(0,
+            Box::new(TestNode::Node(1, Box::new(TestNode::Leaf(2)))),
+            Box::new(TestNode::Leaf(3)));
+
+        assert_eq!(4, size(&tree));
+    }
+
+    #[test]
+    fn test_position() {
+        let tree = TestNode::Two(0,
+            Box::new(TestNode::Node(1, Box::new(TestNode::Leaf(2)))),
+            Box::new(TestNode::Leaf(3)));
+
+        let naps = find_nodes_and_parents(&tree);
+        for (i, nap) in naps.iter().enumerate() {
+            assert_eq!(i, position(nap));
+        }
+    }
 }
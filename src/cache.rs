@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::cmp::max;
+
+/// Cache of fitness results, keyed by the structural hash of a program.
+///
+/// Reproduction copies programs into the next generation unchanged, and
+/// crossover and mutation regularly recreate programs that were seen before.
+/// Pass a cache to `Population::score_cached` to skip evaluating those again.
+///
+/// The cache holds at most `capacity` results. When it is full, the results
+/// that haven't been used for the longest time are evicted.
+pub struct FitnessCache<F> {
+    entries: HashMap<u64, CacheEntry<F>>,
+    capacity: usize,
+    clock: u64,
+
+    /// Number of lookups that found a result
+    pub hits: usize,
+
+    /// Number of lookups that didn't find a result
+    pub misses: usize,
+
+    /// Number of results that were evicted to make room
+    pub evictions: usize
+}
+
+struct CacheEntry<F> {
+    fitness: F,
+    last_used: u64
+}
+
+impl <F: Clone> FitnessCache<F> {
+    pub fn new(capacity: usize) -> FitnessCache<F> {
+        FitnessCache {
+            entries: HashMap::new(),
+            capacity: max(capacity, 1),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0
+        }
+    }
+
+    /// Look up the fitness of the program with the given hash.
+    pub fn get(&mut self, hash: u64) -> Option<F> {
+        self.clock += 1;
+        match self.entries.get_mut(&hash) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.fitness.clone())
+            },
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Store the fitness of the program with the given hash.
+    pub fn insert(&mut self, hash: u64, fitness: F) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&hash) {
+            self.evict();
+        }
+        self.clock += 1;
+        self.entries.insert(hash, CacheEntry { fitness: fitness, last_used: self.clock });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget all results, but keep the statistics.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Fraction of lookups that found a result.
+    pub fn hit_rate(&self) -> f32 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { return 0.0; }
+        self.hits as f32 / lookups as f32
+    }
+
+    /// Evict the least recently used quarter of the cache.
+    ///
+    /// Evicting in batches keeps us from having to search for the oldest entry
+    /// on every insert.
+    fn evict(&mut self) {
+        let mut ages = self.entries.iter().map(|(hash, entry)| (entry.last_used, *hash)).collect::<Vec<_>>();
+        ages.sort();
+        let n = max(self.capacity / 4, 1);
+        for &(_, hash) in ages.iter().take(n) {
+            self.entries.remove(&hash);
+        }
+        self.evictions += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use rand::Rng;
+    use super::super::{Population, seeded_rng};
+    use super::super::genetic::{Fitness, SimpleFitness};
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = FitnessCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        assert_eq!(Some(0), cache.get(0));
+
+        cache.insert(4, 4);
+        assert_eq!(4, cache.len());
+        assert_eq!(None, cache.get(1));
+        assert_eq!(Some(0), cache.get(0));
+        assert_eq!(1, cache.evictions);
+    }
+
+    #[derive(Clone)]
+    enum List {
+        Cons(Box<List>),
+        Nil
+    }
+
+    impl_astnode!(List, 0,
+                  int Cons(next),
+                  leaf Nil());
+
+    #[test]
+    fn test_score_cached_evaluates_programs_once() {
+        let evaluations = AtomicUsize::new(0);
+        let score = |_: &List, _: &mut Rng| {
+            evaluations.fetch_add(1, Ordering::SeqCst);
+            SimpleFitness::new(vec![("score", 1.0)])
+        };
+
+        let mut pop = Population::new(3, 0);
+        pop.add(List::Nil);
+        pop.add(List::Cons(Box::new(List::Nil)));
+        pop.add(List::Nil);
+
+        let mut cache = FitnessCache::new(10);
+        let mut rng = seeded_rng(1);
+        pop.score_cached(&score, &mut cache, &mut rng);
+        assert_eq!(2, evaluations.load(Ordering::SeqCst));
+        assert_eq!(3, pop.scores.len());
+        assert_eq!(1.0, pop.scores[2].score_card().total_score());
+
+        pop.score_cached(&score, &mut cache, &mut rng);
+        assert_eq!(2, evaluations.load(Ordering::SeqCst));
+        assert_eq!(4, cache.hits);
+        assert_eq!(2, cache.misses);
+    }
+}
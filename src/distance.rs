@@ -0,0 +1,355 @@
+use super::AstNode;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Return the tree edit distance between two programs.
+///
+/// This is the smallest number of node insertions, deletions and relabelings
+/// that turns one tree into the other (Zhang & Shasha, 1989). Two nodes have
+/// the same label if they have the same `node_type()` and `label()`, so if
+/// they're the same enum case with the same data fields.
+pub fn tree_edit_distance(ast1: &AstNode, ast2: &AstNode) -> usize {
+    let mut labels = HashMap::new();
+    let t1 = PostOrder::of(ast1, &mut labels);
+    let t2 = PostOrder::of(ast2, &mut labels);
+    let td = tree_distances(&t1, &t2);
+    td.get(t1.len() - 1, t2.len() - 1)
+}
+
+/// A change that turns one tree into another.
+///
+/// Positions are pre-order positions of nodes (see `position()`), either in
+/// the old or in the new tree.
+#[derive(Clone,Debug,PartialEq,RustcEncodable)]
+pub enum Edit {
+    /// Nodes of the old tree were removed. If `size` is 1 and the node had
+    /// children, they take the place of the node.
+    Delete { position: usize, size: usize, label: String },
+
+    /// Nodes of the new tree were added.
+    Insert { position: usize, size: usize, label: String },
+
+    /// A node changed type, enum case or data.
+    Relabel { old_position: usize, new_position: usize, from: String, to: String }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Edit::Delete { position, size, ref label } => write!(f, "- {} {} ({} nodes)", position, label, size),
+            Edit::Insert { position, size, ref label } => write!(f, "+ {} {} ({} nodes)", position, label, size),
+            Edit::Relabel { old_position, new_position, ref from, ref to } => write!(f, "~ {}/{} {} -> {}", old_position, new_position, from, to)
+        }
+    }
+}
+
+/// The differences between two trees.
+#[derive(Clone,Debug,RustcEncodable)]
+pub struct TreeDiff {
+    /// Tree edit distance between the trees
+    pub distance: usize,
+
+    /// Deletions and relabelings in the order of the old tree, followed by
+    /// insertions in the order of the new tree
+    pub edits: Vec<Edit>
+}
+
+impl fmt::Display for TreeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "distance {}", self.distance)?;
+        for edit in self.edits.iter() {
+            write!(f, "\n{}", edit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the changes that turn one tree into the other.
+///
+/// The changes are a smallest edit script, as found by `tree_edit_distance`.
+/// Consecutive deleted or inserted nodes that form a whole subtree are
+/// reported as one edit, for example to show which subtree was replaced by
+/// crossover.
+pub fn tree_diff(ast1: &AstNode, ast2: &AstNode) -> TreeDiff {
+    let mut labels = HashMap::new();
+    let t1 = PostOrder::of(ast1, &mut labels);
+    let t2 = PostOrder::of(ast2, &mut labels);
+    let mut td = tree_distances(&t1, &t2);
+    let mut fd = Matrix::new(t1.len() + 1, t2.len() + 1);
+
+    let mut deleted = vec![false; t1.len()];
+    let mut inserted = vec![false; t2.len()];
+    let mut relabeled = vec![];
+
+    // Backtrack through the forest distances, starting with the whole trees
+    let mut todo = vec![(t1.len() - 1, t2.len() - 1)];
+    while let Some((i, j)) = todo.pop() {
+        forest_distances(&t1, &t2, i, j, &mut td, &mut fd);
+        let li = t1.leftmost[i];
+        let lj = t2.leftmost[j];
+        let (mut dx, mut dy) = (i - li + 1, j - lj + 1);
+        while dx > 0 || dy > 0 {
+            if dx > 0 && fd.get(dx, dy) == fd.get(dx - 1, dy) + 1 {
+                deleted[li + dx - 1] = true;
+                dx -= 1;
+            } else if dy > 0 && fd.get(dx, dy) == fd.get(dx, dy - 1) + 1 {
+                inserted[lj + dy - 1] = true;
+                dy -= 1;
+            } else {
+                let (x, y) = (li + dx - 1, lj + dy - 1);
+                if t1.leftmost[x] == li && t2.leftmost[y] == lj {
+                    if t1.labels[x] != t2.labels[y] {
+                        relabeled.push((x, y));
+                    }
+                    dx -= 1;
+                    dy -= 1;
+                } else {
+                    // Both are subtrees next to the left path, map them separately
+                    todo.push((x, y));
+                    dx = t1.leftmost[x] - li;
+                    dy = t2.leftmost[y] - lj;
+                }
+            }
+        }
+    }
+
+    let mut edits = vec![];
+    for (x, y) in relabeled {
+        edits.push((t1.preorder[x], 0, Edit::Relabel {
+            old_position: t1.preorder[x],
+            new_position: t2.preorder[y],
+            from: t1.nodes[x].label(),
+            to: t2.nodes[y].label()
+        }));
+    }
+    for (x, size) in whole_subtrees(&t1, &deleted) {
+        edits.push((t1.preorder[x], 1, Edit::Delete { position: t1.preorder[x], size: size, label: t1.nodes[x].label() }));
+    }
+    edits.sort_by_key(|&(position, kind, _)| (position, kind));
+    let mut insertions = whole_subtrees(&t2, &inserted).into_iter()
+        .map(|(y, size)| Edit::Insert { position: t2.preorder[y], size: size, label: t2.nodes[y].label() })
+        .collect::<Vec<_>>();
+    insertions.sort_by_key(|e| match *e { Edit::Insert { position, .. } => position, _ => 0 });
+
+    TreeDiff {
+        distance: td.get(t1.len() - 1, t2.len() - 1),
+        edits: edits.into_iter().map(|(_, _, e)| e).chain(insertions).collect()
+    }
+}
+
+/// Group the marked nodes of a tree into subtrees.
+///
+/// Returns the root and size of every largest subtree that is completely
+/// marked, and every other marked node by itself.
+fn whole_subtrees(t: &PostOrder, marked: &[bool]) -> Vec<(usize, usize)> {
+    let whole = (0..t.len())
+        .map(|x| (t.leftmost[x]..x + 1).all(|k| marked[k]))
+        .collect::<Vec<bool>>();
+    (0..t.len())
+        .filter(|&x| marked[x])
+        .filter(|&x| !t.parent[x].map_or(false, |p| whole[p]))
+        .map(|x| (x, if whole[x] { x + 1 - t.leftmost[x] } else { 1 }))
+        .collect()
+}
+
+/// The nodes of a tree in post-order, as used by the Zhang-Shasha algorithm.
+struct PostOrder<'a> {
+    nodes: Vec<&'a AstNode>,
+
+    /// Number identifying the node type and label of every node
+    labels: Vec<usize>,
+
+    /// Index of the leftmost leaf of the subtree of every node
+    leftmost: Vec<usize>,
+
+    /// Nodes that have a left sibling, and the root
+    keyroots: Vec<usize>,
+
+    /// Pre-order position of every node
+    preorder: Vec<usize>,
+
+    /// Parent of every node
+    parent: Vec<Option<usize>>
+}
+
+impl <'a> PostOrder<'a> {
+    /// Number the nodes of a tree. Labels are numbered in the given map, so
+    /// that they can be compared quickly between trees that share the map.
+    fn of(root: &'a AstNode, labels: &mut HashMap<(usize, String), usize>) -> PostOrder<'a> {
+        let mut ret = PostOrder { nodes: vec![], labels: vec![], leftmost: vec![], keyroots: vec![], preorder: vec![], parent: vec![] };
+        ret.walk(root, labels, &mut 0);
+
+        // The keyroot of a leftmost leaf is the highest node that has it
+        let mut highest = BTreeMap::new();
+        for (i, &l) in ret.leftmost.iter().enumerate() {
+            highest.insert(l, i);
+        }
+        ret.keyroots = highest.values().cloned().collect();
+        ret.keyroots.sort();
+        ret
+    }
+
+    fn walk(&mut self, node: &'a AstNode, labels: &mut HashMap<(usize, String), usize>, preorder: &mut usize) -> usize {
+        let position = *preorder;
+        *preorder += 1;
+
+        let children = node.children().into_iter()
+            .map(|child| self.walk(child, labels, preorder))
+            .collect::<Vec<usize>>();
+
+        let i = self.nodes.len();
+        let next_label = labels.len();
+        self.nodes.push(node);
+        self.labels.push(*labels.entry((node.node_type(), node.label())).or_insert(next_label));
+        self.leftmost.push(children.first().map_or(i, |&c| self.leftmost[c]));
+        self.preorder.push(position);
+        self.parent.push(None);
+        for c in children {
+            self.parent[c] = Some(i);
+        }
+        i
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+/// Compute the edit distance between all pairs of subtrees of two trees.
+fn tree_distances(t1: &PostOrder, t2: &PostOrder) -> Matrix {
+    let mut td = Matrix::new(t1.len(), t2.len());
+    let mut fd = Matrix::new(t1.len() + 1, t2.len() + 1);
+    for &i in t1.keyroots.iter() {
+        for &j in t2.keyroots.iter() {
+            forest_distances(t1, t2, i, j, &mut td, &mut fd);
+        }
+    }
+    td
+}
+
+/// Compute the edit distances between the forests of the subtrees at i and j.
+///
+/// Fills in the tree distances of all subtrees along the left paths of i and
+/// j. The forest distances are stored in `fd`, offset by the leftmost leaves.
+fn forest_distances(t1: &PostOrder, t2: &PostOrder, i: usize, j: usize, td: &mut Matrix, fd: &mut Matrix) {
+    let li = t1.leftmost[i];
+    let lj = t2.leftmost[j];
+    for dx in 0..i - li + 2 {
+        fd.set(dx, 0, dx);
+    }
+    for dy in 1..j - lj + 2 {
+        fd.set(0, dy, dy);
+    }
+
+    for x in li..i + 1 {
+        let dx = x - li + 1;
+        for y in lj..j + 1 {
+            let dy = y - lj + 1;
+            let edit = min(fd.get(dx - 1, dy) + 1, fd.get(dx, dy - 1) + 1);
+            if t1.leftmost[x] == li && t2.leftmost[y] == lj {
+                let relabel = if t1.labels[x] == t2.labels[y] { 0 } else { 1 };
+                let d = min(edit, fd.get(dx - 1, dy - 1) + relabel);
+                fd.set(dx, dy, d);
+                td.set(x, y, d);
+            } else {
+                let (px, py) = (t1.leftmost[x] - li, t2.leftmost[y] - lj);
+                let d = min(edit, fd.get(px, py) + td.get(x, y));
+                fd.set(dx, dy, d);
+            }
+        }
+    }
+}
+
+/// Dense matrix of distances.
+struct Matrix {
+    columns: usize,
+    cells: Vec<usize>
+}
+
+impl Matrix {
+    fn new(rows: usize, columns: usize) -> Matrix {
+        Matrix { columns: columns, cells: vec![0; rows * columns] }
+    }
+
+    #[inline]
+    fn get(&self, row: usize, column: usize) -> usize {
+        self.cells[row * self.columns + column]
+    }
+
+    #[inline]
+    fn set(&mut self, row: usize, column: usize, value: usize) {
+        self.cells[row * self.columns + column] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::random_pop::{RandNode, NodeWeights};
+    use super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn node(left: Tree, right: Tree) -> Tree {
+        Tree::Node(Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn test_tree_edit_distance() {
+        let tree = node(node(Tree::Leaf(1), Tree::Leaf(2)), Tree::Leaf(3));
+
+        assert_eq!(0, tree_edit_distance(&tree, &tree.clone()));
+        assert_eq!(1, tree_edit_distance(&tree, &node(node(Tree::Leaf(1), Tree::Leaf(4)), Tree::Leaf(3))));
+        assert_eq!(2, tree_edit_distance(&tree, &node(Tree::Leaf(1), Tree::Leaf(3))));
+        assert_eq!(4, tree_edit_distance(&tree, &Tree::Leaf(3)));
+        assert_eq!(tree_edit_distance(&tree, &Tree::Leaf(1)), tree_edit_distance(&Tree::Leaf(1), &tree));
+    }
+
+    #[test]
+    fn test_tree_diff() {
+        let tree = node(node(Tree::Leaf(1), Tree::Leaf(2)), Tree::Leaf(3));
+
+        let relabel = tree_diff(&tree, &node(node(Tree::Leaf(1), Tree::Leaf(4)), Tree::Leaf(3)));
+        assert_eq!(1, relabel.distance);
+        assert_eq!(vec![Edit::Relabel { old_position: 3, new_position: 3, from: "Leaf(2)".to_string(), to: "Leaf(4)".to_string() }], relabel.edits);
+
+        let replaced = tree_diff(&tree, &node(Tree::Leaf(5), node(Tree::Leaf(6), Tree::Leaf(7))));
+        assert_eq!(replaced.distance, count_edits(&replaced));
+
+        let grown = tree_diff(&Tree::Leaf(3), &tree);
+        assert_eq!(4, grown.distance);
+        assert_eq!(vec![
+            Edit::Insert { position: 0, size: 1, label: "Node".to_string() },
+            Edit::Insert { position: 1, size: 3, label: "Node".to_string() }
+        ], grown.edits);
+        assert_eq!("distance 4\n+ 0 Node (1 nodes)\n+ 1 Node (3 nodes)", format!("{}", grown));
+    }
+
+    #[test]
+    fn test_tree_diff_matches_distance() {
+        let mut rng = seeded_rng(1);
+        for _ in 0..50 {
+            let one: Tree = RandNode::rand(NodeWeights::fixed(4), &mut rng);
+            let two: Tree = RandNode::rand(NodeWeights::fixed(4), &mut rng);
+            let diff = tree_diff(&one, &two);
+            assert_eq!(tree_edit_distance(&one, &two), diff.distance);
+            assert_eq!(diff.distance, count_edits(&diff));
+        }
+    }
+
+    fn count_edits(diff: &TreeDiff) -> usize {
+        diff.edits.iter().map(|e| match *e {
+            Edit::Delete { size, .. } | Edit::Insert { size, .. } => size,
+            Edit::Relabel { .. } => 1
+        }).fold(0, |a, b| a + b)
+    }
+}
@@ -0,0 +1,162 @@
+use super::{AstNode, Number, Population, Fitness};
+use super::ast::{structural_hash, find_nodes_and_parents};
+use super::distance::tree_edit_distance;
+use super::seed::derive_rng;
+use rand::Rng;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// Default number of pairs of programs compared by the run loggers to
+/// estimate the average edit distance.
+pub const DISTANCE_SAMPLE_PAIRS: usize = 100;
+
+/// Measures of how different the programs in a population are.
+///
+/// A population that has converged prematurely has few unique programs, a low
+/// average edit distance and little variation in scores.
+#[derive(Clone,Copy,Debug,Default,PartialEq,RustcEncodable)]
+pub struct Diversity {
+    /// Fraction of programs that are structurally unique
+    pub unique_fraction: Number,
+
+    /// Average tree edit distance between randomly sampled pairs of programs
+    pub mean_edit_distance: Number,
+
+    /// Number of distinct total scores
+    pub distinct_scores: usize,
+
+    /// Shannon entropy of the total scores, in bits
+    pub score_entropy: Number,
+
+    /// Shannon entropy of the frequencies of node types over all programs, in
+    /// bits. Every enum case counts as a separate node type.
+    pub node_type_entropy: Number
+}
+
+impl Diversity {
+    /// Measure the diversity of a population.
+    ///
+    /// The average edit distance is estimated from `sample_pairs` pairs of
+    /// different programs, which are compared in parallel. The pairs are
+    /// picked by a random number generator derived from the generation, so
+    /// measuring doesn't change the course of a run.
+    pub fn of<P, F>(pop: &Population<P, F>, sample_pairs: usize) -> Diversity
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Send
+    {
+        if pop.n() == 0 {
+            return Diversity::default();
+        }
+
+        let unique: HashSet<u64> = pop.population.iter().map(|p| structural_hash(p)).collect();
+
+        // A program is never paired with itself, which would count a distance of 0
+        let mut rng = derive_rng(0, pop.generation, 0);
+        let sample_pairs = if pop.n() < 2 { 0 } else { sample_pairs };
+        let pairs = (0..sample_pairs)
+            .map(|_| {
+                let i = rng.gen_range(0, pop.n());
+                let j = rng.gen_range(0, pop.n() - 1);
+                (i, if j >= i { j + 1 } else { j })
+            })
+            .collect::<Vec<_>>();
+        let programs = &pop.population;
+        let mut distances = Vec::with_capacity(sample_pairs);
+        pairs.par_iter().weight_max()
+            .map(|&(i, j)| tree_edit_distance(&programs[i], &programs[j]))
+            .collect_into(&mut distances);
+        let total_distance = distances.iter().fold(0, |a, b| a + b);
+
+        let scores = frequencies(pop.scores.iter().map(|f| f.score_card().total_score().to_bits()));
+        let node_types = frequencies(pop.population.iter()
+            .flat_map(|p| find_nodes_and_parents(p).into_iter().map(|nap| node_kind(nap.node)).collect::<Vec<_>>()));
+
+        Diversity {
+            unique_fraction: unique.len() as Number / pop.n() as Number,
+            mean_edit_distance: if sample_pairs > 0 { total_distance as Number / sample_pairs as Number } else { 0.0 },
+            distinct_scores: scores.len(),
+            score_entropy: entropy(&scores),
+            node_type_entropy: entropy(&node_types)
+        }
+    }
+}
+
+/// Node type and enum case of a node, without its data fields.
+fn node_kind(node: &AstNode) -> (usize, String) {
+    let label = node.label();
+    let case = label.split('(').next().unwrap_or("").to_string();
+    (node.node_type(), case)
+}
+
+/// Count how often every value occurs.
+fn frequencies<T: Hash+Eq, I: Iterator<Item=T>>(xs: I) -> HashMap<T, usize> {
+    let mut ret = HashMap::new();
+    for x in xs {
+        *ret.entry(x).or_insert(0) += 1;
+    }
+    ret
+}
+
+/// Shannon entropy of a frequency table, in bits.
+fn entropy<T: Hash+Eq>(frequencies: &HashMap<T, usize>) -> Number {
+    let total = frequencies.values().fold(0, |a, b| a + b) as Number;
+    frequencies.values()
+        .map(|&n| n as Number / total)
+        .fold(0.0, |h, p| h - p * p.log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::genetic::SimpleFitness;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn population(programs: Vec<(Tree, f32)>) -> Population<Tree, SimpleFitness> {
+        let mut pop = Population::new(programs.len(), 0);
+        for (program, score) in programs {
+            pop.add(program);
+            pop.scores.push(SimpleFitness::new(vec![("score", score)]));
+        }
+        pop
+    }
+
+    #[test]
+    fn test_converged() {
+        let pop = population(vec![(Tree::Leaf(1), 1.0), (Tree::Leaf(1), 1.0)]);
+        let diversity = Diversity::of(&pop, 10);
+
+        assert_eq!(0.5, diversity.unique_fraction);
+        assert_eq!(0.0, diversity.mean_edit_distance);
+        assert_eq!(1, diversity.distinct_scores);
+        assert_eq!(0.0, diversity.score_entropy);
+        assert_eq!(0.0, diversity.node_type_entropy);
+
+        let pop = population(vec![(Tree::Leaf(1), 1.0)]);
+        assert_eq!(0.0, Diversity::of(&pop, 10).mean_edit_distance);
+    }
+
+    #[test]
+    fn test_diverse() {
+        let node = Tree::Node(Box::new(Tree::Leaf(1)), Box::new(Tree::Leaf(2)));
+        let distance = tree_edit_distance(&Tree::Leaf(1), &node) as Number;
+        let pop = population(vec![(Tree::Leaf(1), 1.0), (node, 2.0)]);
+        let diversity = Diversity::of(&pop, 10);
+
+        // Programs are never compared with themselves
+        assert_eq!(1.0, diversity.unique_fraction);
+        assert_eq!(distance, diversity.mean_edit_distance);
+        assert_eq!(2, diversity.distinct_scores);
+        assert_eq!(1.0, diversity.score_entropy);
+        assert!(diversity.node_type_entropy > 0.0);
+    }
+}
@@ -0,0 +1,438 @@
+use std::any::Any;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use std::u64;
+use rand::Rng;
+use super::genetic::ScoreCard;
+
+/// Number of steps between checks of the clock.
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Budget for the evaluation of a single program.
+///
+/// Evolved programs with loops or deep recursion can run for a very long time.
+/// Thread a budget through the interpreter and call `step()` for every unit of
+/// work (a statement, a function call, a simulation tick). Once `step()`
+/// returns false, the budget is used up and evaluation should stop.
+///
+/// Use `Population::score_with_budget` to give every program its own copy of
+/// the budget, and to find out which programs ran out.
+#[derive(Clone,Debug)]
+pub struct Budget {
+    max_steps: u64,
+    steps: u64,
+    time_limit: Option<Duration>,
+    deadline: Option<Instant>,
+    exhausted: bool
+}
+
+impl Budget {
+    /// A budget of the given number of steps.
+    pub fn steps(max_steps: u64) -> Budget {
+        Budget {
+            max_steps: max_steps,
+            steps: 0,
+            time_limit: None,
+            deadline: None,
+            exhausted: false
+        }
+    }
+
+    /// A budget that never runs out.
+    pub fn unlimited() -> Budget {
+        Budget::steps(u64::MAX)
+    }
+
+    /// Return a copy of this budget that also runs out after the given wall-clock time.
+    ///
+    /// The clock starts running when `start()` is called.
+    pub fn with_time_limit(self, time_limit: Duration) -> Budget {
+        Budget { time_limit: Some(time_limit), ..self }
+    }
+
+    /// Return a fresh copy of this budget, with the clock started.
+    pub fn start(&self) -> Budget {
+        Budget {
+            max_steps: self.max_steps,
+            steps: 0,
+            time_limit: self.time_limit,
+            deadline: self.time_limit.map(|limit| Instant::now() + limit),
+            exhausted: false
+        }
+    }
+
+    /// Spend a single step of the budget.
+    ///
+    /// Returns false if the budget is used up.
+    pub fn step(&mut self) -> bool {
+        if self.exhausted { return false; }
+
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            self.exhausted = true;
+        } else if self.steps % STEPS_PER_CLOCK_CHECK == 0 {
+            if let Some(deadline) = self.deadline {
+                self.exhausted = Instant::now() >= deadline;
+            }
+        }
+        !self.exhausted
+    }
+
+    /// Number of steps taken so far.
+    pub fn steps_taken(&self) -> u64 {
+        self.steps
+    }
+
+    /// Whether the budget has been used up.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+/// What to do when evaluating a program fails.
+///
+/// Used by `Population::try_score`. An evaluation fails if the scoring
+/// function returns an error or panics.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum FailurePolicy {
+    /// Give the program the worst possible score.
+    Worst,
+
+    /// Evaluate the program again, at most the given number of times, before
+    /// giving it the worst possible score.
+    Retry(u32),
+
+    /// Stop scoring and return the failure.
+    Abort
+}
+
+/// Reason why evaluating a program failed.
+#[derive(Debug)]
+pub enum Failure<E> {
+    /// The scoring function returned an error
+    Error(E),
+
+    /// The scoring function panicked with the given message
+    Panic(String)
+}
+
+/// Error returned by `Population::try_score` when the policy is to abort.
+#[derive(Debug)]
+pub struct EvaluationError<E> {
+    /// Index of the program in the population
+    pub index: usize,
+
+    pub failure: Failure<E>
+}
+
+impl <E: fmt::Debug> fmt::Display for EvaluationError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.failure {
+            Failure::Error(ref e) => write!(f, "Evaluating program {} failed: {:?}", self.index, e),
+            Failure::Panic(ref msg) => write!(f, "Evaluating program {} panicked: {}", self.index, msg)
+        }
+    }
+}
+
+/// Settings for scoring with a noisy fitness function.
+///
+/// When the fitness function is noisy (for example because the simulation
+/// starts from random initial conditions), a single evaluation per program
+/// rewards luck. Pass this to `Population::score_resampled` to evaluate every
+/// program a number of times and average the resulting ScoreCards.
+///
+/// Remembers the scores of the previous generation, so that programs that
+/// survive into the next generation unchanged (through reproduction or
+/// elitism) can be recognized.
+pub struct Resampling<F> {
+    /// Number of evaluations per program
+    pub samples: usize,
+
+    /// Whether surviving programs are evaluated again
+    ///
+    /// If true, the new evaluations are averaged together with the ones from
+    /// earlier generations, so the estimate for long-lived programs keeps
+    /// improving. If false, survivors keep their earlier average.
+    pub reevaluate_survivors: bool,
+
+    /// Sum of all ScoreCards, number of samples and the averaged fitness, by structural hash
+    history: HashMap<u64, (ScoreCard, usize, F)>
+}
+
+impl <F> Resampling<F> {
+    pub fn new(samples: usize, reevaluate_survivors: bool) -> Resampling<F> {
+        Resampling {
+            samples: max(samples, 1),
+            reevaluate_survivors: reevaluate_survivors,
+            history: HashMap::new()
+        }
+    }
+
+    /// Return the summed ScoreCards, sample count and averaged fitness of a program in the previous generation.
+    pub fn previous(&self, hash: u64) -> Option<&(ScoreCard, usize, F)> {
+        self.history.get(&hash)
+    }
+
+    /// Replace the remembered generation.
+    pub fn remember(&mut self, history: HashMap<u64, (ScoreCard, usize, F)>) {
+        self.history = history;
+    }
+}
+
+/// Run a fallible scoring function, retrying according to the policy.
+///
+/// Returns the result of the last evaluation and the number of evaluations
+/// that failed.
+pub fn evaluate<P, F, E, S>(scoring_fn: &S, program: &P, policy: FailurePolicy, rng: &mut Rng) -> (Result<F, Failure<E>>, usize)
+    where S: Fn(&P, &mut Rng) -> Result<F, E>
+{
+    let mut failures = 0;
+    loop {
+        let result = match catch_unwind(AssertUnwindSafe(|| scoring_fn(program, rng))) {
+            Ok(Ok(fitness)) => return (Ok(fitness), failures),
+            Ok(Err(e)) => Failure::Error(e),
+            Err(payload) => Failure::Panic(panic_message(payload))
+        };
+        failures += 1;
+
+        match policy {
+            FailurePolicy::Retry(n) if failures <= n as usize => {},
+            _ => return (Err(result), failures)
+        }
+    }
+}
+
+fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        return msg.to_string();
+    }
+    if let Some(msg) = payload.downcast_ref::<String>() {
+        return msg.clone();
+    }
+    "Unknown panic".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use super::super::{Population, Number, seeded_rng, structural_hash};
+    use super::super::genetic::{Fitness, FitnessMut, SimpleFitness};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn score(program: &u32, _: &mut Rng) -> Result<SimpleFitness, String> {
+        match *program {
+            0 => Err("Zero".to_string()),
+            1 => panic!("One"),
+            n => Ok(SimpleFitness::new(vec![("n", n as Number)]))
+        }
+    }
+
+    fn population() -> Population<u32, SimpleFitness> {
+        let mut pop = Population::new(3, 0);
+        for i in 0..3 {
+            pop.add(i);
+        }
+        pop
+    }
+
+    #[test]
+    fn test_failures_get_worst_score() {
+        let mut pop = population();
+        let failures = pop.try_score(score, FailurePolicy::Retry(2), &mut seeded_rng(1)).unwrap();
+
+        assert_eq!(6, failures);
+        assert_eq!(6, pop.failures);
+        assert_eq!(Number::NEG_INFINITY, pop.scores[0].score_card().total_score());
+        assert_eq!(Number::NEG_INFINITY, pop.scores[1].score_card().total_score());
+        assert_eq!(2.0, pop.scores[2].score_card().total_score());
+    }
+
+    #[test]
+    fn test_abort() {
+        let mut pop = population();
+        let error = pop.try_score(score, FailurePolicy::Abort, &mut seeded_rng(1)).err().unwrap();
+
+        assert_eq!(0, error.index);
+        match error.failure {
+            Failure::Error(ref e) => assert_eq!("Zero", e),
+            _ => panic!("Expected an error")
+        }
+    }
+
+    #[test]
+    fn test_abort_skips_remaining_programs() {
+        let mut pop: Population<u32, SimpleFitness> = Population::new(100, 0);
+        for i in 0..100 {
+            pop.add(i);
+        }
+        let evaluated = AtomicUsize::new(0);
+        let error = pop.try_score(|program: &u32, rng: &mut Rng| {
+            evaluated.fetch_add(1, Ordering::SeqCst);
+            score(program, rng)
+        }, FailurePolicy::Abort, &mut seeded_rng(1)).err().unwrap();
+
+        assert_eq!(0, error.index);
+        assert!(evaluated.load(Ordering::SeqCst) < 100);
+    }
+
+    #[test]
+    fn test_budget_runs_out() {
+        let mut budget = Budget::steps(3).start();
+        assert!(budget.step());
+        assert!(budget.step());
+        assert!(budget.step());
+        assert!(!budget.step());
+        assert!(budget.is_exhausted());
+        assert!(!budget.start().is_exhausted());
+    }
+
+    #[test]
+    fn test_exhausted_programs_are_penalized() {
+        fn count_down(program: &u32, _: &mut Rng, budget: &mut Budget) -> SimpleFitness {
+            let mut steps = 0;
+            while steps < *program && budget.step() {
+                steps += 1;
+            }
+            SimpleFitness::new(vec![("steps", steps as Number)])
+        }
+
+        let mut pop = population();
+        pop.score_with_budget(count_down, &Budget::steps(1), &mut seeded_rng(1));
+        assert_eq!(vec![false, false, true], pop.exhausted);
+
+        pop.penalize_exhausted(-10.0);
+        assert_eq!(0.0, pop.scores[0].score_card().total_score());
+        assert_eq!(1.0, pop.scores[1].score_card().total_score());
+        assert_eq!(-9.0, pop.scores[2].score_card().total_score());
+
+        // Penalizing again doesn't count the penalty twice
+        pop.penalize_exhausted(-10.0);
+        assert_eq!(-9.0, pop.scores[2].score_card().total_score());
+        assert_eq!(2, pop.scores[2].score_card().scores().len());
+    }
+
+    #[derive(Clone)]
+    enum Leaf {
+        A,
+        B
+    }
+
+    impl_astnode!(Leaf, 0,
+                  leaf A(),
+                  leaf B());
+
+    fn noise(_: &Leaf, rng: &mut Rng) -> SimpleFitness {
+        SimpleFitness::new(vec![("noise", rng.next_f32())])
+    }
+
+    fn leaves() -> Population<Leaf, SimpleFitness> {
+        let mut pop = Population::new(2, 0);
+        pop.add(Leaf::A);
+        pop.add(Leaf::B);
+        pop
+    }
+
+    #[test]
+    fn test_failures_are_left_out_of_stats() {
+        let mut pop = Population::new(3, 0);
+        pop.add(Leaf::A);
+        pop.add(Leaf::B);
+        pop.add(Leaf::B);
+        pop.try_score(|leaf: &Leaf, _: &mut Rng| -> Result<SimpleFitness, ()> {
+            match *leaf {
+                Leaf::A => panic!("Can't evaluate A"),
+                Leaf::B => Ok(SimpleFitness::minimize(vec![("error", 2.0)]))
+            }
+        }, FailurePolicy::Worst, &mut seeded_rng(1)).unwrap();
+
+        // The failed program loses, and doesn't look like the best when minimizing
+        let failed = pop.scores[0].score_card();
+        assert!(failed.is_failed());
+        assert!(failed < pop.scores[1].score_card());
+        assert_eq!(Number::INFINITY, failed.total_score());
+
+        let stats = pop.stats(0);
+        assert_eq!((2.0, 2.0, 0.0), (stats.total_score.min, stats.total_score.mean, stats.total_score.std_dev));
+        assert_eq!(1, stats.scores.len());
+        assert_eq!(2.0, pop.avg_score());
+    }
+
+    #[test]
+    fn test_resampling_averages() {
+        let mut pop = leaves();
+        let mut resampling = Resampling::new(1000, false);
+        pop.score_resampled(noise, &mut resampling, &mut seeded_rng(1));
+
+        for fitness in pop.scores.iter() {
+            assert!((fitness.score_card().total_score() - 0.5).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_survivors() {
+        let mut resampling = Resampling::new(2, false);
+        let mut pop = leaves();
+        pop.score_resampled(noise, &mut resampling, &mut seeded_rng(1));
+        let first = pop.scores[0].score_card().total_score();
+
+        // Survivors keep their score
+        let mut pop = leaves();
+        pop.score_resampled(noise, &mut resampling, &mut seeded_rng(2));
+        assert_eq!(first, pop.scores[0].score_card().total_score());
+
+        // Unless they're evaluated again
+        resampling.reevaluate_survivors = true;
+        let mut pop = leaves();
+        pop.score_resampled(noise, &mut resampling, &mut seeded_rng(3));
+        assert!(first != pop.scores[0].score_card().total_score());
+        assert_eq!(4, resampling.previous(structural_hash(&Leaf::A)).unwrap().1);
+    }
+
+    #[derive(Clone)]
+    struct Noisy {
+        score_card: ScoreCard,
+        leaf: &'static str
+    }
+
+    impl Fitness for Noisy {
+        fn score_card(&self) -> &ScoreCard { &self.score_card }
+    }
+
+    impl FitnessMut for Noisy {
+        fn score_card_mut(&mut self) -> &mut ScoreCard { &mut self.score_card }
+    }
+
+    #[test]
+    fn test_resampling_keeps_fitness() {
+        fn noisy(leaf: &Leaf, rng: &mut Rng) -> Noisy {
+            let name = match *leaf { Leaf::A => "A", Leaf::B => "B" };
+            Noisy { score_card: ScoreCard::new(vec![("noise", rng.next_f32())]), leaf: name }
+        }
+
+        let mut resampling = Resampling::new(100, false);
+        for seed in 1..3 {
+            let mut pop = Population::new(2, 0);
+            pop.add(Leaf::A);
+            pop.add(Leaf::B);
+            pop.score_resampled(noisy, &mut resampling, &mut seeded_rng(seed));
+
+            // Survivors in the second round keep their fitness, too
+            assert_eq!(vec!["A", "B"], pop.scores.iter().map(|f| f.leaf).collect::<Vec<_>>());
+            assert!((pop.scores[0].score_card().total_score() - 0.5).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let (result, failures) = evaluate(&score, &1, FailurePolicy::Worst, &mut seeded_rng(1));
+        assert_eq!(1, failures);
+        match result {
+            Err(Failure::Panic(ref msg)) => assert_eq!("One", msg),
+            _ => panic!("Expected a panic")
+        }
+    }
+}
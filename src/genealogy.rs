@@ -0,0 +1,246 @@
+use super::{AstNode, Number, Population, Fitness};
+use super::ast::size;
+use std::collections::{HashMap, HashSet};
+
+/// The genetic operator that produced a program.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,RustcEncodable)]
+pub enum Operator {
+    /// Generated at random, or added to the population by hand
+    Random,
+    Reproduce,
+    Mutate,
+    Crossover
+}
+
+/// Where a program came from.
+#[derive(Clone,Debug,PartialEq,RustcEncodable)]
+pub struct Origin {
+    pub operator: Operator,
+
+    /// IDs of the parents. For crossover, the first parent is the one that
+    /// received a subtree from the second.
+    pub parents: Vec<u64>,
+
+    /// Pre-order position (see `position()`) of the mutated node, or of the
+    /// node that was replaced by crossover, if known.
+    pub point: Option<usize>
+}
+
+impl Origin {
+    pub fn random() -> Origin {
+        Origin { operator: Operator::Random, parents: vec![], point: None }
+    }
+
+    pub fn new(operator: Operator, parents: Vec<u64>, point: Option<usize>) -> Origin {
+        Origin { operator: operator, parents: parents, point: point }
+    }
+}
+
+/// A program as recorded in the genealogy.
+#[derive(Clone,Debug,RustcEncodable)]
+pub struct Ancestor {
+    pub id: u64,
+    pub generation: u32,
+    pub origin: Origin,
+
+    /// Total score of the program, if the population was scored
+    pub score: Option<Number>,
+
+    /// Number of nodes in the program
+    pub size: usize,
+
+    /// Label of the root node of the program
+    pub label: String
+}
+
+/// Record of where the programs of a run came from.
+///
+/// Every program in a `Population` has an ID, and `evolve` records in the new
+/// population which parents and operator produced every program. Record every
+/// generation in the genealogy to be able to trace the ancestry of a program
+/// back to the initial population.
+///
+/// Example:
+///
+/// ```ignore
+/// let mut genealogy = Genealogy::new();
+/// for _ in 0..generations {
+///     pop.score(fitness, &mut rng);
+///     genealogy.record(&pop);
+///     pop = evolve(pop, &weights, &mut rng, selector);
+/// }
+///
+/// let champion = pop.ids[0];
+/// println!("{}", genealogy.to_dot(champion));
+/// ```
+pub struct Genealogy {
+    ancestors: HashMap<u64, Ancestor>
+}
+
+impl Genealogy {
+    pub fn new() -> Genealogy {
+        Genealogy { ancestors: HashMap::new() }
+    }
+
+    /// Record all programs in a population.
+    ///
+    /// Call this after scoring, so that the scores are recorded as well.
+    pub fn record<P, F>(&mut self, pop: &Population<P, F>)
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Send
+    {
+        for (i, program) in pop.population.iter().enumerate() {
+            let id = match pop.ids.get(i) { Some(&id) => id, None => continue };
+            self.ancestors.insert(id, Ancestor {
+                id: id,
+                generation: pop.generation,
+                origin: pop.origins.get(i).cloned().unwrap_or_else(Origin::random),
+                score: pop.scores.get(i).map(|f| f.score_card().total_score()),
+                size: size(program),
+                label: program.label()
+            });
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Ancestor> {
+        self.ancestors.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ancestors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ancestors.is_empty()
+    }
+
+    /// Forget all programs from generations before the given one.
+    ///
+    /// Use this to limit the memory used by long runs. Ancestries will stop at
+    /// the forgotten generations.
+    pub fn forget_before(&mut self, generation: u32) {
+        self.ancestors.retain(|_, a| a.generation >= generation);
+    }
+
+    /// Return the program and all its known ancestors, youngest first.
+    pub fn ancestry(&self, id: u64) -> Vec<&Ancestor> {
+        let mut ret = vec![];
+        let mut seen = HashSet::new();
+        let mut todo = vec![id];
+        while !todo.is_empty() {
+            let id = todo.remove(0);
+            if !seen.insert(id) { continue; }
+            if let Some(ancestor) = self.ancestors.get(&id) {
+                todo.extend(ancestor.origin.parents.iter().cloned());
+                ret.push(ancestor);
+            }
+        }
+        ret
+    }
+
+    /// Return the ancestry of a program as a graph in the DOT language.
+    ///
+    /// Render it using Graphviz, for example `dot -Tsvg`.
+    pub fn to_dot(&self, id: u64) -> String {
+        let mut lines = vec!["digraph ancestry {".to_string()];
+        let ancestry = self.ancestry(id);
+        for ancestor in ancestry.iter() {
+            let score = ancestor.score.map_or(String::new(), |s| format!("\\nscore {}", s));
+            lines.push(format!("  n{} [label=\"#{} gen {}\\n{}{}\\nsize {}\"];",
+                               ancestor.id, ancestor.id, ancestor.generation,
+                               ancestor.label.replace("\"", "\\\""), score, ancestor.size));
+        }
+        for ancestor in ancestry.iter() {
+            for (i, parent) in ancestor.origin.parents.iter().enumerate() {
+                if !self.ancestors.contains_key(parent) { continue; }
+                let point = match (i, ancestor.origin.point) {
+                    (0, Some(p)) => format!(" @{}", p),
+                    _ => String::new()
+                };
+                lines.push(format!("  n{} -> n{} [label=\"{:?}{}\"];", parent, ancestor.id, ancestor.origin.operator, point));
+            }
+        }
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::genetic::{SimpleFitness, Weights, TreeLimits, evolve, tournament_selection};
+    use super::super::random_pop::random_population;
+    use super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn score(_: &Tree, _: &mut ::rand::Rng) -> SimpleFitness {
+        SimpleFitness::new(vec![("score", 1.0)])
+    }
+
+    #[test]
+    fn test_id_of() {
+        let mut pop: Population<Tree, SimpleFitness> = Population::new(3, 0);
+        for i in 0..3 {
+            pop.add(Tree::Leaf(i));
+        }
+        pop.ids[2] = 42;
+
+        assert_eq!(Some(2), pop.index_of(&pop.population[2]));
+        assert_eq!(Some(42), pop.id_of(&pop.population[2]));
+
+        // Equal copies and programs outside of the population aren't found
+        let copy = pop.population[0].clone();
+        assert_eq!(None, pop.index_of(&copy));
+        let other = vec![Tree::Leaf(0); 4];
+        assert_eq!(None, pop.index_of(&other[3]));
+    }
+
+    #[test]
+    fn test_ancestry() {
+        let mut rng = seeded_rng(1);
+        let weights = Weights { reproduce: 10, mutate: 30, crossover: 60, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut genealogy = Genealogy::new();
+
+        let mut pop: Population<Tree, SimpleFitness> = random_population(20, 3, &mut rng);
+        let mut total = 0;
+        for _ in 0..3 {
+            pop.score(score, &mut rng);
+            genealogy.record(&pop);
+            total += pop.n();
+            pop = evolve(pop, &weights, &mut rng, |p, r| tournament_selection(2, p, r));
+        }
+        pop.score(score, &mut rng);
+        genealogy.record(&pop);
+        total += pop.n();
+
+        // IDs are unique over the whole run
+        assert_eq!(total, genealogy.len());
+
+        for &id in pop.ids.iter() {
+            let ancestry = genealogy.ancestry(id);
+            assert_eq!(id, ancestry[0].id);
+            assert!(ancestry.iter().any(|a| a.generation == 0));
+
+            let origin = &ancestry[0].origin;
+            match origin.operator {
+                Operator::Reproduce => assert_eq!(1, origin.parents.len()),
+                Operator::Mutate => { assert_eq!(1, origin.parents.len()); assert!(origin.point.is_some()); },
+                Operator::Crossover => { assert_eq!(2, origin.parents.len()); assert!(origin.point.is_some()); },
+                Operator::Random => panic!("Evolved program without parents")
+            }
+        }
+
+        let dot = genealogy.to_dot(pop.ids[0]);
+        assert!(dot.starts_with("digraph ancestry {"));
+        assert!(dot.contains(&format!("n{} [label=", pop.ids[0])));
+    }
+}
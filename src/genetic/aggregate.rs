@@ -0,0 +1,202 @@
+use super::super::num::Number;
+use super::fitness::{ScoreCard, Label, Direction};
+
+/// How the weighted subscores are combined into a single objective.
+#[derive(Clone,Copy,Debug,PartialEq,RustcEncodable)]
+pub enum Combine {
+    Sum,
+
+    /// The worst subscore counts, so every subscore must be good
+    Min,
+
+    /// The best subscore counts
+    Max,
+
+    /// Subscores are always normalized for the product, so that every factor
+    /// is in [0, 1] and a worse subscore always gives a worse objective
+    Product
+}
+
+/// Lowest and highest value of every subscore in a population.
+pub type ScoreRanges = Vec<(Label, Number, Number)>;
+
+/// Configurable way to turn the subscores of a ScoreCard into one objective.
+///
+/// By default a ScoreCard is compared by the sum of its subscores. Aggregate a
+/// population with this to re-balance the subscores before selection, without
+/// having to evaluate the programs again.
+///
+/// Every subscore is multiplied by its weight (1 if not given). If normalized,
+/// subscores are first scaled to [0, 1] using the lowest and highest value of
+/// that subscore in the population.
+///
+/// Minimized subscores are negated, or if normalized, flipped to `1 - x`, so
+/// that a higher objective is always better. `Combine::Product` always
+/// normalizes, and raises every subscore to the power of its weight instead.
+///
+/// Example:
+///
+/// ```
+/// # use moonlander_gp::genetic::{Aggregation, Combine};
+/// let aggregation = Aggregation::new(Combine::Sum)
+///     .with_weight("food_eaten", 2.0)
+///     .with_weight("complexity_penalty", 0.5);
+///
+/// // pop.aggregate(&aggregation);
+/// ```
+#[derive(Clone,Debug,RustcEncodable)]
+pub struct Aggregation {
+    pub method: Combine,
+
+    /// Weights of subscores by label
+    pub weights: Vec<(Label, Number)>,
+
+    /// Weight of subscores that aren't in the weights list
+    pub default_weight: Number,
+
+    /// Scale subscores to [0, 1] over the population before weighing them
+    pub normalize: bool
+}
+
+impl Aggregation {
+    pub fn new(method: Combine) -> Aggregation {
+        Aggregation {
+            method: method,
+            weights: vec![],
+            default_weight: 1.0,
+            normalize: false
+        }
+    }
+
+    /// Return a copy of this aggregation with a weight for the given subscore.
+    pub fn with_weight<L: Into<Label>>(mut self, name: L, weight: Number) -> Aggregation {
+        let name = name.into();
+        self.weights.retain(|&(ref n, _)| *n != name);
+        self.weights.push((name, weight));
+        self
+    }
+
+    /// Return a copy of this aggregation that normalizes subscores.
+    pub fn normalized(mut self) -> Aggregation {
+        self.normalize = true;
+        self
+    }
+
+    pub fn weight_of(&self, name: &str) -> Number {
+        self.weights.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map_or(self.default_weight, |&(_, w)| w)
+    }
+
+    /// Aggregate the subscores of a ScoreCard into a single objective.
+    ///
+    /// The ranges are only used when normalizing.
+    pub fn objective(&self, card: &ScoreCard, ranges: &ScoreRanges) -> Number {
+        let product = self.method == Combine::Product;
+        let values = card.scores().iter().map(|&(ref name, x)| {
+            let minimize = card.direction_of(name) == Direction::Minimize;
+            let x = if self.normalize || product {
+                let n = normalize(x, ranges.iter().find(|&&(ref n, _, _)| n == name));
+                if minimize { 1.0 - n } else { n }
+            } else {
+                if minimize { -x } else { x }
+            };
+            if product { x.powf(self.weight_of(name)) } else { x * self.weight_of(name) }
+        });
+
+        match self.method {
+            Combine::Sum => values.fold(0.0, |a, x| a + x),
+            Combine::Product => values.fold(1.0, |a, x| a * x),
+            Combine::Min => values.fold(None, |a: Option<Number>, x| Some(a.map_or(x, |a| a.min(x)))).unwrap_or(0.0),
+            Combine::Max => values.fold(None, |a: Option<Number>, x| Some(a.map_or(x, |a| a.max(x)))).unwrap_or(0.0)
+        }
+    }
+}
+
+/// Find the lowest and highest finite value of every subscore.
+pub fn score_ranges<'a, I: Iterator<Item=&'a ScoreCard>>(cards: I) -> ScoreRanges {
+    let mut ranges: ScoreRanges = vec![];
+    for card in cards {
+        for &(ref name, x) in card.scores().iter() {
+            if !x.is_finite() { continue; }
+
+            let mut found = false;
+            for range in ranges.iter_mut() {
+                if range.0 == *name {
+                    range.1 = range.1.min(x);
+                    range.2 = range.2.max(x);
+                    found = true;
+                }
+            }
+            if !found {
+                ranges.push((name.clone(), x, x));
+            }
+        }
+    }
+    ranges
+}
+
+fn normalize(x: Number, range: Option<&(Label, Number, Number)>) -> Number {
+    match range {
+        // Values that don't differ don't make a difference either
+        Some(&(_, lo, hi)) if hi > lo => (x - lo) / (hi - lo),
+        _ => 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fitness::ScoreCard;
+
+    #[test]
+    fn test_weights() {
+        let card = ScoreCard::new(vec![("food", 10.0), ("penalty", -4.0)]);
+
+        assert_eq!(6.0, Aggregation::new(Combine::Sum).objective(&card, &vec![]));
+        assert_eq!(18.0, Aggregation::new(Combine::Sum).with_weight("penalty", 0.5).with_weight("food", 2.0).objective(&card, &vec![]));
+        assert_eq!(-4.0, Aggregation::new(Combine::Min).objective(&card, &vec![]));
+    }
+
+    #[test]
+    fn test_product_prefers_better_subscores() {
+        let card = |food, error| ScoreCard::new(vec![("food", food), ("error", error)]).with_direction("error", Direction::Minimize);
+        let cards = vec![card(10.0, 4.0), card(10.0, 2.0), card(20.0, 1.0), card(5.0, 8.0)];
+        let ranges = score_ranges(cards.iter());
+        let product = Aggregation::new(Combine::Product);
+        let objective = |c| product.objective(c, &ranges);
+
+        // Smaller errors and more food are better, even though both errors
+        // would be negative without normalization
+        assert!(objective(&cards[1]) > objective(&cards[0]));
+        assert!(objective(&cards[2]) > objective(&cards[1]));
+        assert_eq!(0.0, objective(&cards[3]));
+        assert_eq!(1.0, objective(&cards[2]));
+
+        // Weights are exponents: the normalized food of the first card is 1/3
+        let weighted = Aggregation::new(Combine::Product).with_weight("food", 2.0);
+        assert!((objective(&cards[0]) / 3.0 - weighted.objective(&cards[0], &ranges)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_normalized() {
+        let card = |food, steps| ScoreCard::new(vec![("food", food), ("steps", steps)]).with_direction("steps", Direction::Minimize);
+        let cards = vec![card(10.0, 100.0), card(20.0, 300.0), card(15.0, 150.0)];
+        let ranges = score_ranges(cards.iter());
+
+        // The number of steps dominates the raw sum, so the first card wins
+        let raw = Aggregation::new(Combine::Sum);
+        assert_eq!(vec![-90.0, -280.0, -135.0], cards.iter().map(|c| raw.objective(c, &ranges)).collect::<Vec<_>>());
+
+        // Normalized, the card that is decent at both wins
+        let normalized = Aggregation::new(Combine::Sum).normalized();
+        assert_eq!(vec![1.0, 1.0, 1.25], cards.iter().map(|c| normalized.objective(c, &ranges)).collect::<Vec<_>>());
+
+        // The product is always normalized
+        let product = Aggregation::new(Combine::Product);
+        assert_eq!(vec![0.0, 0.0, 0.375], cards.iter().map(|c| product.objective(c, &ranges)).collect::<Vec<_>>());
+        assert_eq!(product.objective(&cards[2], &ranges), product.normalized().objective(&cards[2], &ranges));
+
+        assert_eq!(1.0, Aggregation::new(Combine::Max).normalized().objective(&cards[1], &ranges));
+    }
+}
@@ -0,0 +1,322 @@
+use super::Fitness;
+use super::evolve::{Weights, breed};
+use super::crossover::crossover_tree_traced;
+use super::super::{AstNode, Mutatable, Number, Population, Origin, Operator, RandNode};
+use super::super::random_pop::random_population;
+use rand::Rng;
+use std::u32;
+
+/// A program in a layer, with everything the population records about it.
+struct Member<P, F> {
+    program: P,
+    fitness: F,
+    id: u64,
+    origin: Origin,
+    age: u32
+}
+
+/// Age-Layered Population Structure (Hornby, 2006).
+///
+/// The programs are split into layers by age, from young to old. The age of a
+/// program is the number of generations that its oldest genetic material has
+/// been evolving: random programs start at 0, and offspring are one generation
+/// older than their oldest parent. Every `age_gap` generations, the youngest
+/// layer is replaced by new random programs, so that fresh genetic material
+/// keeps entering the run without having to compete with the old programs
+/// right away.
+///
+/// Every layer is evolved by `evolve`'s operators, with parents selected from
+/// the layer itself and the layer below it. The best program of every layer
+/// survives into the next generation. Programs that grow too old for their
+/// layer move up to the next one, where they replace the worst programs.
+///
+/// Example:
+///
+/// ```ignore
+/// let mut alps: Alps<Program, SimpleFitness> = Alps::new(5, 100, 10, 6, &mut rng);
+/// for _ in 0..generations {
+///     alps.score(fitness, &mut rng);
+///     alps.evolve(&weights, &mut rng, |p, r| tournament_selection(5, p, r));
+/// }
+/// ```
+pub struct Alps<P: Clone+Sync, F: Fitness+Sized+Send> {
+    /// Layers from youngest to oldest
+    pub layers: Vec<Population<P, F>>,
+
+    /// Maximum age of the programs in every layer. Programs in the oldest
+    /// layer can get any age.
+    pub age_limits: Vec<u32>,
+
+    /// Maximum number of programs in a layer
+    pub layer_size: usize,
+
+    /// Number of generations between injections of random programs
+    pub age_gap: u32,
+
+    /// Maximum depth of the random programs
+    pub max_depth: usize,
+
+    pub generation: u32,
+
+    next_id: u64
+}
+
+impl <P, F> Alps<P, F>
+    where P: AstNode+RandNode+Clone+Mutatable+Sync,
+          F: Fitness+Clone+Send
+{
+    /// Create layers with a random youngest layer and empty older layers.
+    ///
+    /// The age limits grow polynomially with the layers: `age_gap` times 1, 2,
+    /// 4, 9, 16 and so on. The older layers fill up as programs age.
+    pub fn new<R: Rng>(layers: usize, layer_size: usize, age_gap: u32, max_depth: usize, rng: &mut R) -> Alps<P, F> {
+        assert!(layers > 0, "ALPS needs at least one layer");
+        let age_limits = (0..layers as u32)
+            .map(|i| if i + 1 == layers as u32 { u32::MAX } else { age_gap * if i < 2 { i + 1 } else { i * i } })
+            .collect();
+
+        let mut alps = Alps {
+            layers: (0..layers).map(|_| Population::new(layer_size, 0)).collect(),
+            age_limits: age_limits,
+            layer_size: layer_size,
+            age_gap: age_gap,
+            max_depth: max_depth,
+            generation: 0,
+            next_id: 0
+        };
+        alps.layers[0] = alps.random_layer(rng);
+        alps
+    }
+
+    /// Total number of programs in all layers.
+    pub fn n(&self) -> usize {
+        self.layers.iter().fold(0, |n, l| n + l.n())
+    }
+
+    /// Score the programs in all layers.
+    pub fn score<S>(&mut self, scoring_fn: S, rng: &mut Rng)
+        where S: Fn(&P, &mut Rng) -> F + Sync
+    {
+        for layer in self.layers.iter_mut() {
+            if layer.n() > 0 {
+                layer.score(&scoring_fn, rng);
+            }
+        }
+    }
+
+    /// Evolve all layers into the next generation.
+    ///
+    /// The layers must be scored. Programs that are too old for their layer
+    /// move up first. Every `age_gap` generations, the whole youngest layer
+    /// moves up, to make room for random programs. Then every layer that has
+    /// programs is replaced by its best program and offspring, selected by
+    /// `selector` from the layer and the one below it.
+    pub fn evolve<S, R: Rng>(&mut self, weights: &Weights, rng: &mut R, selector: S)
+        where S: for<'a> Fn(&'a Population<P, F>, &mut Rng) -> &'a P
+    {
+        let generation = self.generation + 1;
+        let inject = self.age_gap > 0 && generation % self.age_gap == 0;
+        self.promote(inject);
+
+        let mut next = Vec::with_capacity(self.layers.len());
+        for i in 0..self.layers.len() {
+            if self.layers[i].n() == 0 {
+                next.push(Population::new(self.layer_size, generation));
+                continue;
+            }
+
+            let mut pool = Population::new(2 * self.layer_size, self.generation);
+            pool.next_id = self.next_id;
+            for layer in self.layers[i.saturating_sub(1)..i + 1].iter() {
+                for member in members(layer) {
+                    push(&mut pool, member);
+                }
+            }
+
+            let mut layer = breed(&pool, self.layer_size.saturating_sub(1), weights, rng, &selector, |one, two, rng| {
+                let (children, points) = crossover_tree_traced(one, two, rng);
+                (children, Some(points))
+            });
+
+            // The best program of the layer survives, aging like a reproduced
+            // program. Crossover can make one offspring too many, which makes
+            // room for it.
+            truncate(&mut layer, self.layer_size.saturating_sub(1));
+            let elites = &self.layers[i];
+            for e in elites.best_indexes(1) {
+                let origin = Origin::new(Operator::Reproduce, vec![elites.ids[e]], None);
+                layer.add_with_origin(elites.population[e].clone(), origin, elites.ages[e] + 1);
+            }
+            self.next_id = layer.next_id;
+            next.push(layer);
+        }
+        self.layers = next;
+        self.generation = generation;
+
+        if inject {
+            self.layers[0] = self.random_layer(rng);
+        }
+    }
+
+    /// Move programs that are too old for their layer to the next layer, and
+    /// the whole youngest layer if it is about to be replaced.
+    ///
+    /// A layer that gets too full keeps its best programs.
+    fn promote(&mut self, inject: bool) {
+        for i in (0..self.layers.len() - 1).rev() {
+            let limit = self.age_limits[i];
+            let (young, old) = take_members(&mut self.layers[i]).into_iter()
+                .partition::<Vec<_>, _>(|m| m.age <= limit && !(i == 0 && inject));
+            for member in young {
+                push(&mut self.layers[i], member);
+            }
+            if old.is_empty() { continue; }
+
+            let mut above = take_members(&mut self.layers[i + 1]);
+            above.extend(old);
+            above.sort_by(|a, b| b.fitness.score_card().cmp(a.fitness.score_card()));
+            above.truncate(self.layer_size);
+            for member in above {
+                push(&mut self.layers[i + 1], member);
+            }
+        }
+    }
+
+    /// Create a layer of random programs, with IDs that are unique in all layers.
+    fn random_layer<R: Rng>(&mut self, rng: &mut R) -> Population<P, F> {
+        let mut layer: Population<P, F> = random_population(self.layer_size, self.max_depth, rng);
+        layer.generation = self.generation;
+        for id in layer.ids.iter_mut() {
+            *id = self.next_id;
+            self.next_id += 1;
+        }
+        layer.next_id = self.next_id;
+        layer
+    }
+
+    /// The best program in any layer, with its fitness.
+    pub fn best(&self) -> Option<(&P, &F)> {
+        self.layers.iter()
+            .flat_map(|l| l.population.iter().zip(l.scores.iter()))
+            .max_by_key(|&(_, f)| f.score_card())
+    }
+
+    /// Total score of the best program in any layer.
+    pub fn best_score(&self) -> Number {
+        self.best().map_or(0.0, |(_, f)| f.score_card().total_score())
+    }
+}
+
+/// Copy the scored programs of a layer.
+fn members<P, F>(layer: &Population<P, F>) -> Vec<Member<P, F>>
+    where P: Clone+Sync,
+          F: Fitness+Clone+Send
+{
+    (0..layer.scores.len()).map(|i| Member {
+        program: layer.population[i].clone(),
+        fitness: layer.scores[i].clone(),
+        id: layer.ids[i],
+        origin: layer.origins[i].clone(),
+        age: layer.ages[i]
+    }).collect()
+}
+
+/// Take all scored programs out of a layer.
+fn take_members<P, F>(layer: &mut Population<P, F>) -> Vec<Member<P, F>>
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    let n = layer.scores.len();
+    let ret = layer.population.drain(..).zip(layer.scores.drain(..))
+        .zip(layer.ids.drain(..).zip(layer.origins.drain(..)).zip(layer.ages.drain(..)))
+        .take(n)
+        .map(|((program, fitness), ((id, origin), age))| Member { program: program, fitness: fitness, id: id, origin: origin, age: age })
+        .collect();
+    layer.exhausted.clear();
+    ret
+}
+
+/// Remove the last programs of a layer until it has at most `n` programs.
+fn truncate<P, F>(layer: &mut Population<P, F>, n: usize)
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    layer.population.truncate(n);
+    layer.scores.truncate(n);
+    layer.ids.truncate(n);
+    layer.origins.truncate(n);
+    layer.ages.truncate(n);
+}
+
+/// Add a scored program to a layer, keeping its ID.
+fn push<P, F>(layer: &mut Population<P, F>, member: Member<P, F>)
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    layer.population.push(member.program);
+    layer.scores.push(member.fitness);
+    layer.ids.push(member.id);
+    layer.origins.push(member.origin);
+    layer.ages.push(member.age);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{SimpleFitness, TreeLimits, tournament_selection};
+    use super::super::super::seed::seeded_rng;
+    use std::collections::HashSet;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn score(tree: &Tree, _: &mut Rng) -> SimpleFitness {
+        SimpleFitness::new(vec![("size", super::super::super::size(tree) as Number)])
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut rng = seeded_rng(5);
+        let weights = Weights { reproduce: 10, mutate: 30, crossover: 60, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut alps: Alps<Tree, SimpleFitness> = Alps::new(3, 10, 3, 3, &mut rng);
+        assert_eq!(vec![3, 6, u32::MAX], alps.age_limits);
+        assert_eq!(10, alps.n());
+
+        for generation in 1..20 {
+            alps.score(score, &mut rng);
+            alps.evolve(&weights, &mut rng, |p, r| tournament_selection(2, p, r));
+
+            // Programs move up as they age, and new random programs come in at the bottom
+            assert!(alps.layers.iter().all(|l| l.n() <= alps.layer_size));
+            if generation % 3 == 0 {
+                assert!(alps.layers[0].ages.iter().all(|&a| a == 0));
+            }
+        }
+        alps.score(score, &mut rng);
+        assert!(alps.layers.iter().all(|l| l.n() > 0));
+        assert!(alps.best_score() > 0.0);
+
+        let ids = alps.layers.iter().flat_map(|l| l.ids.iter().cloned()).collect::<HashSet<u64>>();
+        assert_eq!(alps.n(), ids.len());
+    }
+
+    #[test]
+    fn test_best_of_layer_survives() {
+        let mut rng = seeded_rng(5);
+        let weights = Weights { reproduce: 0, mutate: 100, crossover: 0, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut alps: Alps<Tree, SimpleFitness> = Alps::new(2, 10, 3, 3, &mut rng);
+        alps.score(score, &mut rng);
+        let best = alps.best().unwrap().0.clone();
+
+        alps.evolve(&weights, &mut rng, |p, r| tournament_selection(2, p, r));
+        assert_eq!(10, alps.layers[0].n());
+        assert!(alps.layers[0].population.contains(&best));
+    }
+}
@@ -0,0 +1,114 @@
+use std::cmp::max;
+use rand::Rng;
+use super::super::{AstNode, Mutatable};
+use super::crossover::crossover_tree;
+use super::fitness::Fitness;
+
+/// Brood recombination (Tackett, 1994).
+///
+/// Produce `brood_size` pairs of children from the same two parents using
+/// `crossover_tree`, score every child and return the best two. Culling the
+/// brood this way weeds out most children that are worse than their parents
+/// before they ever make it into the population.
+///
+/// The scoring function has the same shape as the one passed to
+/// `Population::score`. Since every member of the brood gets evaluated, it can
+/// pay off to pass a cheaper version of the fitness function here, for example
+/// one that only runs a subset of the fitness cases.
+pub fn brood_crossover<T, F, S, R>(ast1: &T, ast2: &T, brood_size: usize, scoring_fn: &S, rng: &mut R) -> (Box<T>, Box<T>)
+    where T: AstNode+Mutatable+Clone,
+          F: Fitness,
+          S: Fn(&T, &mut Rng) -> F,
+          R: Rng+Sized
+{
+    let mut brood = Vec::with_capacity(2 * brood_size);
+    for _ in 0..max(brood_size, 1) {
+        let (child1, child2) = crossover_tree(ast1, ast2, rng);
+        brood.push(child1);
+        brood.push(child2);
+    }
+
+    let mut scored = brood.into_iter().map(|child| (scoring_fn(child.as_ref(), rng), child)).collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.0.score_card().cmp(a.0.score_card()));
+
+    let mut best = scored.into_iter().map(|(_, child)| child);
+    let child1 = best.next().unwrap();
+    let child2 = best.next().unwrap();
+    (child1, child2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{SimpleFitness, Weights, TreeLimits, evolve_with, tournament_selection};
+    use super::super::super::{Number, Population, size};
+    use super::super::super::random_pop::random_population;
+    use super::super::super::seed::seeded_rng;
+    use std::cell::RefCell;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn node(left: Tree, right: Tree) -> Tree {
+        Tree::Node(Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn test_returns_best_of_brood() {
+        let one = node(Tree::Leaf(1), node(Tree::Leaf(2), node(Tree::Leaf(3), Tree::Leaf(4))));
+        let two = node(node(Tree::Leaf(5), Tree::Leaf(6)), Tree::Leaf(7));
+        let mut rng = seeded_rng(1);
+
+        for &brood_size in [1, 3, 10].iter() {
+            let scored = RefCell::new(vec![]);
+            let score = |tree: &Tree, _: &mut Rng| {
+                scored.borrow_mut().push(size(tree));
+                SimpleFitness::new(vec![("size", size(tree) as Number)])
+            };
+
+            let (child1, child2) = brood_crossover(&one, &two, brood_size, &score, &mut rng);
+
+            // Every member of the brood is scored, and the two best survive
+            let mut sizes = scored.into_inner();
+            assert_eq!(2 * brood_size, sizes.len());
+            sizes.sort_by(|a, b| b.cmp(a));
+            assert_eq!(sizes[0], size(child1.as_ref()));
+            assert_eq!(sizes[1], size(child2.as_ref()));
+        }
+    }
+
+    #[test]
+    fn test_empty_brood_still_crosses_over() {
+        let count = RefCell::new(0);
+        let score = |_: &Tree, _: &mut Rng| {
+            *count.borrow_mut() += 1;
+            SimpleFitness::new(vec![("score", 1.0)])
+        };
+        brood_crossover(&Tree::Leaf(1), &Tree::Leaf(2), 0, &score, &mut seeded_rng(1));
+        assert_eq!(2, count.into_inner());
+    }
+
+    #[test]
+    fn test_evolve_with_custom_crossover() {
+        let mut rng = seeded_rng(1);
+        let weights = Weights { reproduce: 0, mutate: 0, crossover: 100, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut pop: Population<Tree, SimpleFitness> = random_population(10, 2, &mut rng);
+        pop.score(|_, _| SimpleFitness::new(vec![("score", 1.0)]), &mut rng);
+
+        let calls = RefCell::new(0);
+        let pop = evolve_with(pop, &weights, &mut rng, |p, r| tournament_selection(2, p, r), |_, _, _| {
+            *calls.borrow_mut() += 1;
+            (Box::new(Tree::Leaf(42)), Box::new(Tree::Leaf(43)))
+        });
+
+        assert_eq!(5, calls.into_inner());
+        assert!(pop.population.iter().all(|p| *p == Tree::Leaf(42) || *p == Tree::Leaf(43)));
+    }
+}
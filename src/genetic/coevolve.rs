@@ -0,0 +1,285 @@
+use super::Fitness;
+use super::evolve::{Weights, evolve};
+use super::super::{AstNode, Mutatable, Population};
+use rand::Rng;
+use std::mem;
+
+/// How to pick the opponents a program is scored against.
+///
+/// Scoring against only the current generation of the other population can
+/// make the populations cycle: both keep beating strategies that the other
+/// has already forgotten. Scoring against the best of the previous generation
+/// or against the hall of fame of the whole run gives a more stable target.
+///
+/// The hall of fame holds the best program of every generation (Rosin &
+/// Belew, 1997), not the programs with the highest scores, since scores
+/// against different opponents can't be compared between generations.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum OpponentSampling {
+    /// This many programs at random from the current generation of the
+    /// other population, picked for every program separately
+    Random(usize),
+
+    /// The best programs of the previous generation of the other population
+    BestOfLastGeneration(usize),
+
+    /// This many programs at random from the hall of fame of the other
+    /// population, picked for every program separately
+    HallOfFame(usize)
+}
+
+/// Competitive co-evolution of two populations.
+///
+/// For problems like games, where the fitness of a program depends on whom it
+/// plays against, two populations evolve side by side, such as predators and
+/// prey. Every program is scored against a sample of opponents from the
+/// other population. As long as there is no previous generation or the hall
+/// of fame is still empty, opponents are picked at random instead.
+///
+/// Example:
+///
+/// ```ignore
+/// let mut coevolution = Coevolution::new(predators, prey, OpponentSampling::HallOfFame(5), Some(50));
+/// for _ in 0..generations {
+///     coevolution.score(chase, flee, &mut rng);
+///     coevolution.evolve(&weights, &mut rng, selector, selector);
+/// }
+/// ```
+pub struct Coevolution<P: Clone+Sync, F: Fitness+Sized+Send, Q: Clone+Sync, G: Fitness+Sized+Send> {
+    pub first: Population<P, F>,
+    pub second: Population<Q, G>,
+    pub sampling: OpponentSampling,
+
+    /// The best program of every generation of the first population, oldest first
+    pub first_hall_of_fame: Vec<P>,
+
+    /// The best program of every generation of the second population, oldest first
+    pub second_hall_of_fame: Vec<Q>,
+
+    /// Maximum number of programs in each hall of fame, if any. When a hall
+    /// of fame is full, the oldest programs are forgotten first.
+    pub hall_of_fame_size: Option<usize>,
+
+    first_best: Vec<P>,
+    second_best: Vec<Q>
+}
+
+impl <P, F, Q, G> Coevolution<P, F, Q, G>
+    where P: AstNode+Clone+Mutatable+Sync,
+          F: Fitness+Send,
+          Q: AstNode+Clone+Mutatable+Sync,
+          G: Fitness+Send
+{
+    pub fn new(first: Population<P, F>, second: Population<Q, G>, sampling: OpponentSampling, hall_of_fame_size: Option<usize>) -> Coevolution<P, F, Q, G> {
+        Coevolution {
+            first: first,
+            second: second,
+            sampling: sampling,
+            first_hall_of_fame: vec![],
+            second_hall_of_fame: vec![],
+            hall_of_fame_size: hall_of_fame_size,
+            first_best: vec![],
+            second_best: vec![]
+        }
+    }
+
+    /// Score both populations against opponents from the other one.
+    ///
+    /// `first_fn` scores a program of the first population against a sample
+    /// of opponents from the second population, and `second_fn` the other way
+    /// around. Programs are scored in parallel like by `Population::score`,
+    /// and the opponents are picked with the random number generator of the
+    /// program, so scoring is reproducible. Afterwards, the best program of
+    /// this generation is added to the hall of fame of its population.
+    ///
+    /// Like `Population::score`, this takes the random number generator as a
+    /// trait object, since it is passed on to the scoring functions.
+    pub fn score<S, T>(&mut self, first_fn: S, second_fn: T, rng: &mut Rng)
+        where S: Fn(&P, &[&Q], &mut Rng) -> F + Sync,
+              T: Fn(&Q, &[&P], &mut Rng) -> G + Sync
+    {
+        {
+            let sampling = self.sampling;
+            let (others, best, hall) = (&self.second.population, &self.second_best, &self.second_hall_of_fame);
+            self.first.score(|program, rng| {
+                let opponents = sample_opponents(sampling, others, best, hall, rng);
+                first_fn(program, &opponents, rng)
+            }, rng);
+        }
+        {
+            let sampling = self.sampling;
+            let (others, best, hall) = (&self.first.population, &self.first_best, &self.first_hall_of_fame);
+            self.second.score(|program, rng| {
+                let opponents = sample_opponents(sampling, others, best, hall, rng);
+                second_fn(program, &opponents, rng)
+            }, rng);
+        }
+
+        enter_hall_of_fame(&mut self.first_hall_of_fame, &self.first, self.hall_of_fame_size);
+        enter_hall_of_fame(&mut self.second_hall_of_fame, &self.second, self.hall_of_fame_size);
+        if let OpponentSampling::BestOfLastGeneration(n) = self.sampling {
+            self.first_best = self.first.best_n(n.min(self.first.n()));
+            self.second_best = self.second.best_n(n.min(self.second.n()));
+        }
+    }
+
+    /// Evolve both populations into the next generation with `evolve`.
+    ///
+    /// Like `evolve`, this is generic over the random number generator, so
+    /// that the same generator can be passed to `score` as `&mut rng`.
+    pub fn evolve<S, T, R: Rng>(&mut self, weights: &Weights, rng: &mut R, first_selector: S, second_selector: T)
+        where S: for<'a> Fn(&'a Population<P, F>, &mut Rng) -> &'a P,
+              T: for<'a> Fn(&'a Population<Q, G>, &mut Rng) -> &'a Q
+    {
+        let first = mem::replace(&mut self.first, Population::new(0, 0));
+        self.first = evolve(first, weights, rng, first_selector);
+        let second = mem::replace(&mut self.second, Population::new(0, 0));
+        self.second = evolve(second, weights, rng, second_selector);
+    }
+}
+
+/// Add the best program of a scored population to a hall of fame.
+fn enter_hall_of_fame<T, F>(hall: &mut Vec<T>, pop: &Population<T, F>, size: Option<usize>)
+    where T: Clone+Sync,
+          F: Fitness+Send
+{
+    if pop.scores.is_empty() || size == Some(0) { return; }
+    hall.extend(pop.best_n(1));
+    if let Some(size) = size {
+        if hall.len() > size {
+            let excess = hall.len() - size;
+            hall.drain(..excess);
+        }
+    }
+}
+
+/// Pick the opponents for a single program.
+fn sample_opponents<'a, T>(sampling: OpponentSampling, others: &'a [T], best: &'a [T], hall: &'a [T], rng: &mut Rng) -> Vec<&'a T>
+    where T: AstNode+Clone+Sync
+{
+    let pick = |from: &'a [T], n: usize, rng: &mut Rng| -> Vec<&'a T> {
+        if from.is_empty() { return vec![]; }
+        (0..n).map(|_| &from[rng.next_u32() as usize % from.len()]).collect()
+    };
+
+    match sampling {
+        OpponentSampling::BestOfLastGeneration(_) if !best.is_empty() => best.iter().collect(),
+        OpponentSampling::HallOfFame(n) if !hall.is_empty() => pick(hall, n, rng),
+        OpponentSampling::Random(n) | OpponentSampling::BestOfLastGeneration(n) | OpponentSampling::HallOfFame(n) => pick(others, n, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{SimpleFitness, TreeLimits, tournament_selection};
+    use super::super::super::random_pop::random_population;
+    use super::super::super::seed::seeded_rng;
+    use super::super::super::{Number, size};
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    /// Bigger trees beat smaller ones.
+    fn play(program: &Tree, opponents: &[&Tree], _: &mut Rng) -> SimpleFitness {
+        let wins = opponents.iter().filter(|o| size(program) > size(**o)).count();
+        SimpleFitness::new(vec![("wins", wins as Number)])
+    }
+
+    fn run(sampling: OpponentSampling) -> (Vec<Number>, Vec<Number>) {
+        let mut rng = seeded_rng(1);
+        let weights = Weights { reproduce: 10, mutate: 30, crossover: 60, tree_height: 3, limits: TreeLimits::unlimited() };
+        let first = random_population(10, 2, &mut rng);
+        let second = random_population(10, 2, &mut rng);
+        let mut coevolution: Coevolution<Tree, SimpleFitness, Tree, SimpleFitness> = Coevolution::new(first, second, sampling, Some(3));
+
+        for _ in 0..3 {
+            coevolution.score(play, play, &mut rng);
+            coevolution.evolve(&weights, &mut rng, |p, r| tournament_selection(2, p, r), |p, r| tournament_selection(2, p, r));
+        }
+        coevolution.score(play, play, &mut rng);
+
+        assert_eq!(3, coevolution.first.generation);
+        assert_eq!(3, coevolution.first_hall_of_fame.len());
+        assert_eq!(3, coevolution.second_hall_of_fame.len());
+        (coevolution.first.scores.iter().map(|f| f.score_card().total_score()).collect(),
+         coevolution.second.scores.iter().map(|f| f.score_card().total_score()).collect())
+    }
+
+    #[test]
+    fn test_sampling_strategies() {
+        for &sampling in [OpponentSampling::Random(4), OpponentSampling::BestOfLastGeneration(4), OpponentSampling::HallOfFame(4)].iter() {
+            let (first, second) = run(sampling);
+            assert!(first.iter().chain(second.iter()).all(|&wins| wins <= 4.0));
+
+            // Scoring is reproducible
+            assert_eq!((first, second), run(sampling));
+        }
+    }
+
+    #[test]
+    fn test_best_of_last_generation() {
+        let mut first = Population::new(3, 0);
+        let mut second = Population::new(3, 0);
+        for i in 1..4 {
+            first.add(Tree::Leaf(i));
+            second.add(Tree::Leaf(i));
+        }
+        let mut coevolution: Coevolution<Tree, SimpleFitness, Tree, SimpleFitness> = Coevolution::new(first, second, OpponentSampling::BestOfLastGeneration(2), Some(0));
+        let mut rng = seeded_rng(1);
+        let score = |program: &Tree, _: &[&Tree], _: &mut Rng| SimpleFitness::new(vec![("score", match *program { Tree::Leaf(x) => x as Number, _ => 0.0 })]);
+        coevolution.score(&score, &score, &mut rng);
+
+        let opponents = sample_opponents(coevolution.sampling, &coevolution.second.population, &coevolution.second_best, &coevolution.second_hall_of_fame, &mut rng);
+        assert_eq!(vec![&Tree::Leaf(2), &Tree::Leaf(3)], opponents);
+    }
+
+    #[test]
+    fn test_hall_of_fame() {
+        let champion = Tree::Node(Box::new(Tree::Leaf(7)), Box::new(Tree::Leaf(7)));
+        let hall = vec![champion.clone()];
+        let others = vec![Tree::Leaf(1), Tree::Leaf(2)];
+        let mut rng = seeded_rng(1);
+
+        // Opponents come from the hall of fame, not from the current generation
+        let opponents = sample_opponents(OpponentSampling::HallOfFame(3), &others, &[], &hall, &mut rng);
+        assert_eq!(vec![&champion, &champion, &champion], opponents);
+
+        // Until there is a hall of fame
+        let empty = vec![];
+        let opponents = sample_opponents(OpponentSampling::HallOfFame(3), &others, &[], &empty, &mut rng);
+        assert_eq!(3, opponents.len());
+        assert!(opponents.iter().all(|o| others.contains(o)));
+    }
+
+    #[test]
+    fn test_hall_of_fame_keeps_every_generation() {
+        let leaves = |xs: &[u32]| {
+            let mut pop = Population::new(xs.len(), 0);
+            for &x in xs { pop.add(Tree::Leaf(x)); }
+            pop
+        };
+        let score = |program: &Tree, _: &[&Tree], _: &mut Rng| SimpleFitness::new(vec![("score", match *program { Tree::Leaf(x) => x as Number, _ => 0.0 })]);
+        let mut coevolution: Coevolution<Tree, SimpleFitness, Tree, SimpleFitness> = Coevolution::new(leaves(&[5, 1]), leaves(&[1]), OpponentSampling::HallOfFame(1), Some(2));
+        let mut rng = seeded_rng(1);
+
+        // Later champions get in, even with lower scores than earlier ones
+        for champions in [[2, 1], [3, 2]].iter() {
+            coevolution.score(&score, &score, &mut rng);
+            coevolution.first = leaves(champions);
+        }
+        assert_eq!(vec![Tree::Leaf(5), Tree::Leaf(2)], coevolution.first_hall_of_fame);
+
+        // The oldest champions are forgotten first
+        coevolution.score(&score, &score, &mut rng);
+        assert_eq!(vec![Tree::Leaf(2), Tree::Leaf(3)], coevolution.first_hall_of_fame);
+        assert_eq!(2, coevolution.second_hall_of_fame.len());
+    }
+}
@@ -6,13 +6,25 @@ use std::collections::btree_map::Entry;
 use std::rc::Rc;
 use rand;
 use super::super::{AstNode, Mutatable};
-use super::super::ast::{NodeInTree, find_nodes_and_parents, replace_to_root};
+use super::super::ast::{NodeInTree, find_nodes_and_parents, replace_to_root, depth, size, position};
+use super::super::num::Number;
+
+/// Number of times a limited crossover is retried before giving up.
+const MAX_CROSSOVER_ATTEMPTS: usize = 10;
 
 /// Cross two trees.
 ///
 /// Pick two random subtrees of the same type in both trees, and return two new
 /// trees with the subtrees switched.
 pub fn crossover_tree<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, rng: &mut R) -> (Box<T>, Box<T>) {
+    crossover_tree_traced(ast1, ast2, rng).0
+}
+
+/// Cross two trees, and return the crossover points.
+///
+/// Like `crossover_tree`, but also returns the pre-order positions (see
+/// `position()`) of the swapped subtrees in the first and second tree.
+pub fn crossover_tree_traced<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, rng: &mut R) -> ((Box<T>, Box<T>), (usize, usize)) {
     let nodes1 = group_by_type(find_nodes_and_parents(ast1));
     let nodes2 = group_by_type(find_nodes_and_parents(ast2));
 
@@ -29,9 +41,192 @@ pub fn crossover_tree<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T,
     let child1 = replace_to_root::<T>(&nap1, nap2.node.copy());
     let child2 = replace_to_root::<T>(&nap2, nap1.node.copy());
 
+    ((child1, child2), (position(nap1), position(nap2)))
+}
+
+/// Cross two trees, keeping the offspring within a maximum depth and size.
+///
+/// Repeats `crossover_tree` until both children are at most `max_depth` deep
+/// and contain at most `max_size` nodes. If that doesn't succeed within a
+/// couple of attempts, copies of the parents are returned instead.
+pub fn crossover_tree_limited<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, max_depth: usize, max_size: usize, rng: &mut R) -> (Box<T>, Box<T>) {
+    for _ in 0..MAX_CROSSOVER_ATTEMPTS {
+        let (child1, child2) = crossover_tree(ast1, ast2, rng);
+        if within_limits(child1.as_ref(), max_depth, max_size) && within_limits(child2.as_ref(), max_depth, max_size) {
+            return (child1, child2);
+        }
+    }
+    (Box::new(ast1.clone()), Box::new(ast2.clone()))
+}
+
+/// Size-fair crossover.
+///
+/// Like `crossover_tree`, but the subtree picked from the second parent is of
+/// similar size as the one picked from the first parent (Langdon, 2000). The
+/// second crossover point is drawn from the subtrees that are smaller, equally
+/// large or larger than the first one, weighted such that the expected change
+/// in size of the offspring is zero. This keeps program sizes from drifting
+/// upwards over the generations.
+pub fn size_fair_crossover<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, rng: &mut R) -> (Box<T>, Box<T>) {
+    let nodes1 = group_by_type(find_nodes_and_parents(ast1));
+    let nodes2 = group_by_type(find_nodes_and_parents(ast2));
+
+    let shared_node_types = nodes1.iter()
+            .filter_map(|(typ, _)| nodes2.get(typ).map(|_| *typ))
+            .collect::<Vec<usize>>();
+    let typ = rng.choose(&shared_node_types).unwrap();
+
+    let nap1 = rng.choose(&nodes1.get(typ).unwrap()).unwrap();
+    let size1 = size(nap1.node);
+
+    let sized2 = nodes2.get(typ).unwrap().iter().map(|nap| (nap, size(nap.node))).collect::<Vec<_>>();
+    let nap2 = size_fair_choice(size1, &sized2, rng);
+
+    let child1 = replace_to_root::<T>(&nap1, nap2.node.copy());
+    let child2 = replace_to_root::<T>(&nap2, nap1.node.copy());
+
     (child1, child2)
 }
 
+/// One-point crossover (Poli & Langdon, 1998).
+///
+/// Walks both parents from the root at the same time to find their common
+/// region: the positions at which both trees have a node of the same type,
+/// reached through parents of equal arity. The crossover point is picked from
+/// that region, so the swapped subtrees occupy the same place in both parents.
+pub fn one_point_crossover<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, rng: &mut R) -> (Box<T>, Box<T>) {
+    let region = common_region(ast1, ast2);
+    let &(ref nap1, ref nap2) = rng.choose(&region).unwrap();
+
+    let child1 = replace_to_root::<T>(nap1, nap2.node.copy());
+    let child2 = replace_to_root::<T>(nap2, nap1.node.copy());
+
+    (child1, child2)
+}
+
+/// Uniform crossover (Poli & Langdon, 1998).
+///
+/// Walks the common region of both parents and swaps every pair of nodes in it
+/// with a probability of 1/2. Nodes in the interior of the region (equal arity
+/// and children of matching types) only exchange the node itself and keep their
+/// children, nodes on the boundary of the region exchange their entire subtree.
+pub fn uniform_crossover<T: AstNode+Mutatable+Clone, R: rand::Rng+Sized>(ast1: &T, ast2: &T, rng: &mut R) -> (Box<T>, Box<T>) {
+    let (child1, child2) = uniform_swap(ast1, ast2, rng);
+    (child1.downcast::<T>().ok().unwrap(), child2.downcast::<T>().ok().unwrap())
+}
+
+/// Return all pairs of nodes in the common region of two trees.
+fn common_region<'a>(ast1: &'a AstNode, ast2: &'a AstNode) -> Vec<(Rc<NodeInTree<'a>>, Rc<NodeInTree<'a>>)> {
+    let mut result = vec![];
+    common_region_into(Rc::new(NodeInTree { node: ast1, root_path: None }),
+                       Rc::new(NodeInTree { node: ast2, root_path: None }),
+                       &mut result);
+    result
+}
+
+fn common_region_into<'a>(nap1: Rc<NodeInTree<'a>>,
+                          nap2: Rc<NodeInTree<'a>>,
+                          acc: &mut Vec<(Rc<NodeInTree<'a>>, Rc<NodeInTree<'a>>)>) {
+    let children1 = nap1.node.children();
+    let children2 = nap2.node.children();
+    acc.push((nap1.clone(), nap2.clone()));
+
+    if children1.len() != children2.len() { return; }
+    for (child1, child2) in children1.into_iter().zip(children2) {
+        if child1.node_type() == child2.node_type() {
+            common_region_into(Rc::new(NodeInTree { node: child1, root_path: Some(nap1.clone()) }),
+                               Rc::new(NodeInTree { node: child2, root_path: Some(nap2.clone()) }),
+                               acc);
+        }
+    }
+}
+
+fn uniform_swap<R: rand::Rng+Sized>(node1: &AstNode, node2: &AstNode, rng: &mut R) -> (Box<AstNode>, Box<AstNode>) {
+    let children1 = node1.children();
+    let children2 = node2.children();
+    let interior = children1.len() == children2.len()
+        && children1.iter().zip(children2.iter()).all(|(c1, c2)| c1.node_type() == c2.node_type());
+
+    if !interior {
+        return if rng.gen() { (node2.copy(), node1.copy()) } else { (node1.copy(), node2.copy()) };
+    }
+
+    let (new_children1, new_children2): (Vec<_>, Vec<_>) = children1.into_iter().zip(children2)
+        .map(|(c1, c2)| uniform_swap(c1, c2, rng))
+        .unzip();
+
+    if rng.gen() {
+        (with_children(node2, new_children1), with_children(node1, new_children2))
+    } else {
+        (with_children(node1, new_children1), with_children(node2, new_children2))
+    }
+}
+
+/// Return a copy of a node with all of its children replaced.
+fn with_children(node: &AstNode, children: Vec<Box<AstNode>>) -> Box<AstNode> {
+    let mut result = node.copy();
+    for (i, child) in children.into_iter().enumerate() {
+        let mut new_child = Some(child);
+        result = {
+            let old_child = result.children()[i];
+            result.replace_child(old_child, &mut new_child)
+        };
+    }
+    result
+}
+
+/// Whether a tree fits within the given depth and size.
+fn within_limits(tree: &AstNode, max_depth: usize, max_size: usize) -> bool {
+    depth(tree) <= max_depth && size(tree) <= max_size
+}
+
+/// Pick one of the given subtrees for size-fair crossover with a subtree of
+/// `size1` nodes.
+fn size_fair_choice<X: Clone, R: rand::Rng>(size1: usize, sized: &[(X, usize)], rng: &mut R) -> X {
+    // Never consider subtrees more than twice as large as the first one, unless
+    // there is nothing else to pick.
+    let mut candidates = sized.iter().filter(|&&(_, s)| s <= 2 * size1 + 1).cloned().collect::<Vec<_>>();
+    if candidates.is_empty() {
+        candidates = sized.to_vec();
+    }
+
+    let smaller = candidates.iter().filter(|&&(_, s)| s < size1).cloned().collect::<Vec<_>>();
+    let equal = candidates.iter().filter(|&&(_, s)| s == size1).cloned().collect::<Vec<_>>();
+    let larger = candidates.iter().filter(|&&(_, s)| s > size1).cloned().collect::<Vec<_>>();
+
+    let p_equal = if equal.is_empty() {
+        0.0
+    } else if smaller.is_empty() && larger.is_empty() {
+        1.0
+    } else {
+        1.0 / size1 as Number
+    };
+    let p_larger = if larger.is_empty() {
+        0.0
+    } else if smaller.is_empty() {
+        1.0 - p_equal
+    } else {
+        let mean_smaller = mean_size_difference(&smaller, size1);
+        let mean_larger = mean_size_difference(&larger, size1);
+        (1.0 - p_equal) * mean_smaller / (mean_smaller + mean_larger)
+    };
+
+    let r = rng.gen::<Number>();
+    let class = if r < p_equal {
+        &equal
+    } else if r < p_equal + p_larger {
+        &larger
+    } else {
+        &smaller
+    };
+    rng.choose(class).unwrap().0.clone()
+}
+
+fn mean_size_difference<X>(naps: &[(X, usize)], size: usize) -> Number {
+    let total = naps.iter().map(|&(_, s)| if s > size { s - size } else { size - s }).fold(0, |a, b| a + b);
+    total as Number / naps.len() as Number
+}
+
 fn group_by_type(naps: Vec<Rc<NodeInTree>>) -> BTreeMap<usize, Vec<Rc<NodeInTree>>> {
     let mut ret : BTreeMap<usize, Vec<Rc<NodeInTree>>> = BTreeMap::new();
     for nap in naps {
@@ -43,3 +238,134 @@ fn group_by_type(naps: Vec<Rc<NodeInTree>>) -> BTreeMap<usize, Vec<Rc<NodeInTree
     }
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::{depth, size};
+    use super::super::super::random_pop::{RandNode, NodeWeights};
+    use super::super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn node(left: Tree, right: Tree) -> Tree {
+        Tree::Node(Box::new(left), Box::new(right))
+    }
+
+    /// Full binary tree of the given depth, with leaves numbered from `first`.
+    fn full(depth: usize, first: &mut u32) -> Tree {
+        if depth <= 1 {
+            *first += 1;
+            return Tree::Leaf(*first - 1);
+        }
+        let left = full(depth - 1, first);
+        node(left, full(depth - 1, first))
+    }
+
+    #[test]
+    fn test_limited_crossover_respects_limits() {
+        // Only swaps of subtrees at the same level fit the parents' own size
+        let one = full(4, &mut 0);
+        let two = full(4, &mut 100);
+        let (max_depth, max_size) = (4, 15);
+        let fits = |tree: &Tree| depth(tree) <= max_depth && size(tree) <= max_size;
+
+        let mut rng = seeded_rng(1);
+        let violations = (0..200)
+            .filter(|_| {
+                let (child1, child2) = crossover_tree(&one, &two, &mut rng);
+                !fits(&child1) || !fits(&child2)
+            })
+            .count();
+
+        let mut fallbacks = 0;
+        for _ in 0..200 {
+            let (child1, child2) = crossover_tree_limited(&one, &two, max_depth, max_size, &mut rng);
+            assert!(fits(&child1) && fits(&child2));
+            if *child1 == one && *child2 == two {
+                fallbacks += 1;
+            }
+        }
+
+        assert!(violations > 0);
+        assert!(fallbacks > 0);
+        assert!(fallbacks < 200);
+    }
+
+    #[test]
+    fn test_size_fair_choice() {
+        let sizes = [1, 1, 3, 5, 7, 9, 31];
+        let sized = sizes.iter().map(|&s| (s, s)).collect::<Vec<_>>();
+        let mut rng = seeded_rng(1);
+
+        let draws = 2000;
+        let mut total_change = 0;
+        for _ in 0..draws {
+            let chosen = size_fair_choice(3, &sized, &mut rng);
+            // Never more than twice as large as the first subtree
+            assert!(chosen <= 2 * 3 + 1);
+            total_change += chosen as i32 - 3;
+        }
+
+        // The expected change in size is zero
+        assert!((total_change as Number / draws as Number).abs() < 0.3);
+    }
+
+    #[test]
+    fn test_size_fair_crossover_conserves_total_size() {
+        let mut rng = seeded_rng(1);
+        for _ in 0..100 {
+            let one: Tree = RandNode::rand(NodeWeights::fixed(6), &mut rng);
+            let two: Tree = RandNode::rand(NodeWeights::fixed(6), &mut rng);
+
+            let (child1, child2) = size_fair_crossover(&one, &two, &mut rng);
+            assert_eq!(size(&one) + size(&two), size(child1.as_ref()) + size(child2.as_ref()));
+        }
+    }
+
+    #[test]
+    fn test_one_point_crossover_stays_in_common_region() {
+        let one = node(Tree::Leaf(1), node(Tree::Leaf(2), Tree::Leaf(3)));
+        let two = node(node(Tree::Leaf(4), Tree::Leaf(5)), Tree::Leaf(6));
+
+        // The common region is the root and its two children.
+        let mut rng = seeded_rng(1);
+        for _ in 0..20 {
+            let (child1, child2) = one_point_crossover(&one, &two, &mut rng);
+            let swapped_all = *child1 == two && *child2 == one;
+            let swapped_left = *child1 == node(node(Tree::Leaf(4), Tree::Leaf(5)), node(Tree::Leaf(2), Tree::Leaf(3)))
+                && *child2 == node(Tree::Leaf(1), Tree::Leaf(6));
+            let swapped_right = *child1 == node(Tree::Leaf(1), Tree::Leaf(6))
+                && *child2 == node(node(Tree::Leaf(4), Tree::Leaf(5)), node(Tree::Leaf(2), Tree::Leaf(3)));
+            assert!(swapped_all || swapped_left || swapped_right);
+        }
+    }
+
+    #[test]
+    fn test_uniform_crossover_swaps_labels_in_place() {
+        let one = node(Tree::Leaf(1), Tree::Leaf(2));
+        let two = node(Tree::Leaf(3), Tree::Leaf(4));
+
+        let mut rng = seeded_rng(1);
+        for _ in 0..20 {
+            let (child1, child2) = uniform_crossover(&one, &two, &mut rng);
+            match (*child1, *child2) {
+                (Tree::Node(a1, b1), Tree::Node(a2, b2)) => {
+                    let lefts = (*a1, *a2);
+                    let rights = (*b1, *b2);
+                    assert!(lefts == (Tree::Leaf(1), Tree::Leaf(3)) || lefts == (Tree::Leaf(3), Tree::Leaf(1)));
+                    assert!(rights == (Tree::Leaf(2), Tree::Leaf(4)) || rights == (Tree::Leaf(4), Tree::Leaf(2)));
+                },
+                _ => panic!("Shape of the trees should be preserved")
+            }
+        }
+    }
+}
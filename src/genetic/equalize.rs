@@ -0,0 +1,190 @@
+use std::cmp::max;
+use super::fitness::Fitness;
+use super::super::{AstNode, Number, Population};
+use super::super::ast::size;
+use super::super::num::{sum, partial_min};
+
+/// Number of offspring in a row that may be rejected before the next one is
+/// accepted regardless of its size.
+///
+/// Prevents evolution from getting stuck when the genetic operators are unable
+/// to produce programs of the sizes that still have room.
+const MAX_CONSECUTIVE_REJECTIONS: usize = 1000;
+
+/// Dynamic operator equalization (Silva & Dignum, 2009).
+///
+/// Programs are divided into bins by their size. Every generation, each bin
+/// gets a capacity that is proportional to the average score of the programs
+/// in that bin, and new offspring are only accepted into the population if
+/// their bin still has room. Bins that were empty in the previous generation
+/// get a capacity of 1, so that the size distribution can slowly shift
+/// towards larger or smaller programs if those turn out to be better.
+///
+/// To use, put an equalizer in the initial population. `evolve` will carry it
+/// over to every next generation:
+///
+/// ```ignore
+/// let mut pop = random_population(POPULATION_SIZE, MAX_DEPTH, &mut rng);
+/// pop.equalizer = Some(Equalizer::new(5));
+/// ```
+#[derive(Clone,Debug)]
+pub struct Equalizer {
+    bin_width: usize,
+
+    /// Remaining room in every bin
+    capacities: Vec<usize>,
+
+    /// Number of programs accepted into every bin
+    histogram: Vec<usize>,
+
+    rejected_in_a_row: usize,
+
+    /// Number of offspring that were rejected
+    pub rejections: usize,
+
+    /// Number of offspring that were accepted without room in their bin
+    pub forced: usize
+}
+
+impl Equalizer {
+    /// Create an equalizer that puts programs in bins of `bin_width` sizes.
+    pub fn new(bin_width: usize) -> Equalizer {
+        Equalizer {
+            bin_width: max(bin_width, 1),
+            capacities: vec![],
+            histogram: vec![],
+            rejected_in_a_row: 0,
+            rejections: 0,
+            forced: 0
+        }
+    }
+
+    /// Return a fresh equalizer with bin capacities based on the given scored population.
+    pub fn for_next_generation<P, F>(&self, pop: &Population<P, F>) -> Equalizer
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Send
+    {
+        let bins = pop.population.iter().map(|p| self.bin(size(p))).collect::<Vec<usize>>();
+        let nbins = bins.iter().max().map_or(0, |b| b + 1);
+
+        let mut counts = vec![0; nbins];
+        let mut totals = vec![0.0; nbins];
+        for (i, &bin) in bins.iter().enumerate() {
+            counts[bin] += 1;
+            if let Some(fitness) = pop.scores.get(i) {
+                totals[bin] += fitness.score_card().objective();
+            }
+        }
+
+        let averages = (0..nbins)
+            .map(|b| if counts[b] > 0 { Some(totals[b] / counts[b] as Number) } else { None })
+            .collect::<Vec<Option<Number>>>();
+        let lowest = partial_min(averages.iter().filter_map(|a| *a)).unwrap_or(0.0);
+        let weights = averages.iter().map(|a| a.map_or(0.0, |a| a - lowest)).collect::<Vec<Number>>();
+        let total_weight = sum(weights.iter().cloned());
+
+        let capacities = (0..nbins).map(|b| {
+            if counts[b] == 0 {
+                1
+            } else if total_weight > 0.0 {
+                max(1, (pop.n() as Number * weights[b] / total_weight).round() as usize)
+            } else {
+                counts[b]
+            }
+        }).collect();
+
+        Equalizer {
+            bin_width: self.bin_width,
+            capacities: capacities,
+            histogram: vec![0; nbins],
+            rejected_in_a_row: 0,
+            rejections: 0,
+            forced: 0
+        }
+    }
+
+    /// Decide whether a program of the given size is admitted into the population.
+    ///
+    /// Takes up room in the program's bin if it is.
+    pub fn accept(&mut self, size: usize) -> bool {
+        let bin = self.bin(size);
+        if bin >= self.capacities.len() {
+            self.capacities.resize(bin + 1, 1);
+            self.histogram.resize(bin + 1, 0);
+        }
+
+        if self.capacities[bin] > 0 {
+            self.capacities[bin] -= 1;
+        } else if self.rejected_in_a_row + 1 >= MAX_CONSECUTIVE_REJECTIONS {
+            self.forced += 1;
+        } else {
+            self.rejected_in_a_row += 1;
+            self.rejections += 1;
+            return false;
+        }
+
+        self.rejected_in_a_row = 0;
+        self.histogram[bin] += 1;
+        true
+    }
+
+    /// Number of accepted programs per bin.
+    ///
+    /// Bin `i` contains the programs with sizes from `i * bin_width + 1` up to
+    /// and including `(i + 1) * bin_width`.
+    pub fn histogram(&self) -> &[usize] {
+        &self.histogram
+    }
+
+    /// Remaining room per bin.
+    pub fn capacities(&self) -> &[usize] {
+        &self.capacities
+    }
+
+    fn bin(&self, size: usize) -> usize {
+        (max(size, 1) - 1) / self.bin_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::SimpleFitness;
+    use super::super::super::Population;
+
+    #[derive(Clone)]
+    enum List {
+        Cons(Box<List>),
+        Nil
+    }
+
+    impl_astnode!(List, 0,
+                  int Cons(next),
+                  leaf Nil());
+
+    fn list(n: usize) -> List {
+        (0..n).fold(List::Nil, |tail, _| List::Cons(Box::new(tail)))
+    }
+
+    #[test]
+    fn test_better_bins_get_more_room() {
+        let mut pop = Population::new(4, 0);
+        for &(length, score) in [(0, 1.0), (0, 1.0), (5, 3.0), (5, 3.0)].iter() {
+            pop.add(list(length));
+            pop.scores.push(SimpleFitness::new(vec![("score", score)]));
+        }
+
+        let equalizer = Equalizer::new(2).for_next_generation(&pop);
+        assert_eq!(&[1, 1, 4], equalizer.capacities());
+    }
+
+    #[test]
+    fn test_accept_until_full() {
+        let mut equalizer = Equalizer::new(1);
+        assert!(equalizer.accept(3));
+        assert!(!equalizer.accept(3));
+        assert!(equalizer.accept(1));
+        assert_eq!(&[1, 0, 1], equalizer.histogram());
+        assert_eq!(1, equalizer.rejections);
+    }
+}
@@ -1,55 +1,191 @@
 use super::Fitness;
-use super::super::Population;
+use super::super::{Population, Origin, Operator};
 use super::super::{AstNode, Mutatable};
 use super::crossover;
 use super::mutate;
+use super::limits::TreeLimits;
+use super::equalize::Equalizer;
+use super::super::ast::size;
 use rand::Rng;
+use std::time::Instant;
 
 /// Parameters to the `evolve` function.
 pub struct Weights {
     pub reproduce: u32,
     pub mutate: u32,
     pub crossover: u32,
-    pub tree_height: i32
+    pub tree_height: i32,
+
+    /// Limits on the offspring of mutation and crossover
+    pub limits: TreeLimits
+}
+
+impl Weights {
+    /// Create weights without limits on the offspring.
+    pub fn new(reproduce: u32, mutate: u32, crossover: u32, tree_height: i32) -> Weights {
+        Weights {
+            reproduce: reproduce,
+            mutate: mutate,
+            crossover: crossover,
+            tree_height: tree_height,
+            limits: TreeLimits::unlimited()
+        }
+    }
+
+    /// Set the limits on the offspring of mutation and crossover.
+    pub fn with_limits(mut self, limits: TreeLimits) -> Weights {
+        self.limits = limits;
+        self
+    }
 }
 
 /// Evolve an old generation into a new generation.
 ///
 /// Uses the parameters in the `Weights` structure and the selection algorithm
 /// to pick and evolve individuals from the given population into a new
-/// one.
+/// one. Offspring that exceed `weights.limits` are handled according to the
+/// limit policy, and statistics about this are recorded in the `limit_stats`
+/// of the new population. If the population has an `equalizer`, offspring are
+/// only admitted if there is room for programs of their size.
+///
+/// The parents, operator and crossover or mutation point of every program are
+/// recorded in the `origins` of the new population (see `Genealogy`).
 pub fn evolve<P, F, S, R: Rng>(pop: Population<P, F>, weights: &Weights, rng: &mut R, selector: S) -> Population<P, F>
     where P: AstNode+Clone+Mutatable+Sync,
           F: Fitness+Send,
           S: for<'a> Fn(&'a Population<P, F>, &mut Rng) -> &'a P
 {
-    let mut ret = Population::new(pop.n(), pop.generation + 1);
-    while ret.n() < pop.n() {
+    let n = pop.n();
+    breed(&pop, n, weights, rng, selector, |one, two, rng| {
+        let (children, points) = crossover::crossover_tree_traced(one, two, rng);
+        (children, Some(points))
+    })
+}
+
+/// Evolve an old generation into a new generation, with a custom crossover operator.
+///
+/// Works like `evolve`, but calls `crossover_fn` on the two selected parents
+/// instead of `crossover_tree`. Use this to plug in one of the other crossover
+/// operators, for example brood recombination:
+///
+/// ```ignore
+/// let brood = |one: &Program, two: &Program, rng: &mut StdRng| brood_crossover(one, two, 5, &quick_fitness, rng);
+/// pop = evolve_with(pop, &weights, &mut rng, selector, brood);
+/// ```
+pub fn evolve_with<P, F, S, C, R: Rng>(pop: Population<P, F>, weights: &Weights, rng: &mut R, selector: S, mut crossover_fn: C) -> Population<P, F>
+    where P: AstNode+Clone+Mutatable+Sync,
+          F: Fitness+Send,
+          S: for<'a> Fn(&'a Population<P, F>, &mut Rng) -> &'a P,
+          C: FnMut(&P, &P, &mut R) -> (Box<P>, Box<P>)
+{
+    let n = pop.n();
+    breed(&pop, n, weights, rng, selector, |one, two, rng| (crossover_fn(one, two, rng), None))
+}
+
+/// Breed `n` programs from a population, with a crossover operator that may
+/// report its crossover points.
+///
+/// The origin of every program in the new population is recorded, with the
+/// IDs of its parents. Offspring are one generation older than their oldest
+/// parent.
+pub fn breed<P, F, S, C, R: Rng>(pop: &Population<P, F>, n: usize, weights: &Weights, rng: &mut R, selector: S, mut crossover_fn: C) -> Population<P, F>
+    where P: AstNode+Clone+Mutatable+Sync,
+          F: Fitness+Send,
+          S: for<'a> Fn(&'a Population<P, F>, &mut Rng) -> &'a P,
+          C: FnMut(&P, &P, &mut R) -> ((Box<P>, Box<P>), Option<(usize, usize)>)
+{
+    let start = Instant::now();
+    let mut ret = Population::new(n, pop.generation + 1);
+    ret.next_id = pop.next_id;
+    let mut equalizer = pop.equalizer.as_ref().map(|e| e.for_next_generation(pop));
+    while ret.n() < n {
         pick![rng,
             weights.reproduce, {
-                let winner = selector(&pop, rng);
-                ret.add(winner.clone());
+                let winner = selector(pop, rng);
+                let origin = Origin::new(Operator::Reproduce, parent_ids(pop, &[winner]), None);
+                admit(&mut ret, &mut equalizer, winner.clone(), origin, child_age(pop, &[winner]));
             },
             weights.mutate, {
-                let winner = selector(&pop, rng);
-                let target_height = rng.next_u32() as i32 % weights.tree_height;
-                let mutation = mutate::mutate_tree(winner, target_height, rng);
-                ret.add(*mutation);
+                let winner = selector(pop, rng);
+                let returned = ret.limit_stats.parents_returned;
+                let mut point = None;
+                let mutation = weights.limits.apply(winner, &mut ret.limit_stats, rng, |rng| {
+                    let target_height = rng.next_u32() as i32 % weights.tree_height;
+                    let (mutation, p) = mutate::mutate_tree_traced(winner, target_height, rng);
+                    point = Some(p);
+                    mutation
+                });
+                let origin = if ret.limit_stats.parents_returned > returned {
+                    Origin::new(Operator::Reproduce, parent_ids(pop, &[winner]), None)
+                } else {
+                    Origin::new(Operator::Mutate, parent_ids(pop, &[winner]), point)
+                };
+                admit(&mut ret, &mut equalizer, *mutation, origin, child_age(pop, &[winner]));
             },
             weights.crossover, {
                 if pop.n() < 2 { continue; }
 
-                let one = selector(&pop, rng);
-                let two = selector(&pop, rng);
+                let one = selector(pop, rng);
+                let two = selector(pop, rng);
 
-                let (child1, child2) = crossover::crossover_tree(one, two, rng);
+                let mut points = None;
+                let ((child1, child2), (returned1, returned2)) = weights.limits.apply_pair_traced(one, two, &mut ret.limit_stats, rng, |rng| {
+                    let (children, p) = crossover_fn(one, two, rng);
+                    points = p;
+                    children
+                });
 
                 // We insert both children, this might make the population go over size, but never
                 // by more than 1.
-                ret.add(*child1);
-                ret.add(*child2);
+                let (origin1, age1) = crossover_origin(pop, one, two, returned1, points.map(|p| p.0));
+                let (origin2, age2) = crossover_origin(pop, two, one, returned2, points.map(|p| p.1));
+                admit(&mut ret, &mut equalizer, *child1, origin1, age1);
+                admit(&mut ret, &mut equalizer, *child2, origin2, age2);
             }
         ];
     }
+    ret.equalizer = equalizer;
+    ret.variation_time = start.elapsed();
     ret
 }
+
+/// Look up the IDs of the selected parents.
+fn parent_ids<P, F>(pop: &Population<P, F>, parents: &[&P]) -> Vec<u64>
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    parents.iter().filter_map(|p| pop.id_of(p)).collect()
+}
+
+/// Origin and age of a child of crossover, which is a copy of `parent` if it
+/// was returned in place of a child that didn't fit the limits.
+fn crossover_origin<P, F>(pop: &Population<P, F>, parent: &P, other: &P, returned: bool, point: Option<usize>) -> (Origin, u32)
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    if returned {
+        (Origin::new(Operator::Reproduce, parent_ids(pop, &[parent]), None), child_age(pop, &[parent]))
+    } else {
+        (Origin::new(Operator::Crossover, parent_ids(pop, &[parent, other]), point), child_age(pop, &[parent, other]))
+    }
+}
+
+/// Age of the offspring of the selected parents.
+fn child_age<P, F>(pop: &Population<P, F>, parents: &[&P]) -> u32
+    where P: Clone+Sync,
+          F: Fitness+Send
+{
+    1 + parents.iter()
+        .filter_map(|p| pop.index_of(p).and_then(|i| pop.ages.get(i).cloned()))
+        .max().unwrap_or(0)
+}
+
+/// Add a program to the new population, if the equalizer has room for it.
+fn admit<P, F>(ret: &mut Population<P, F>, equalizer: &mut Option<Equalizer>, program: P, origin: Origin, age: u32)
+    where P: AstNode+Clone+Sync,
+          F: Fitness+Send
+{
+    if equalizer.as_mut().map_or(true, |e| e.accept(size(&program))) {
+        ret.add_with_origin(program, origin, age);
+    }
+}
@@ -1,6 +1,9 @@
 use super::super::num::Number;
+use super::aggregate::{Aggregation, ScoreRanges};
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::ops::Add;
+use rustc_serialize::{Encodable, Encoder};
 
 /// Trait that models fitness for an individual
 ///
@@ -12,9 +15,27 @@ pub trait Fitness: Send {
     fn score_card(&self) -> &ScoreCard;
 }
 
+/// Fitness that can be made from just a ScoreCard.
+///
+/// Needed for operations that have to come up with a fitness without running
+/// the fitness function, like giving a program whose evaluation failed the
+/// worst possible score.
+pub trait FromScoreCard: Fitness {
+    fn from_score_card(score_card: ScoreCard) -> Self;
+}
+
+/// Fitness of which the ScoreCard can be changed after evaluation.
+///
+/// Needed for operations that adjust the scores of a whole population, like
+/// re-weighting the subscores.
+pub trait FitnessMut: Fitness {
+    fn score_card_mut(&mut self) -> &mut ScoreCard;
+}
+
 /// Simple fitness result that only consists of a ScoreCard.
 ///
 /// In case you don't need to retain any additional state, you can use this struct.
+#[derive(Clone,RustcEncodable)]
 pub struct SimpleFitness {
     score_card: ScoreCard
 }
@@ -23,63 +44,298 @@ impl SimpleFitness {
     pub fn new(scores: Scores) -> SimpleFitness {
         SimpleFitness { score_card: ScoreCard::new(scores) }
     }
+
+    /// Create a fitness of which the total score should be minimized.
+    pub fn minimize(scores: Scores) -> SimpleFitness {
+        SimpleFitness { score_card: ScoreCard::minimize(scores) }
+    }
+
+    /// Create a fitness with labels that can be made at runtime.
+    pub fn from_labels<L: Into<Label>>(scores: Vec<(L, Number)>) -> SimpleFitness {
+        SimpleFitness { score_card: ScoreCard::from_labels(scores) }
+    }
+
+    /// Create a fitness with labels that can be made at runtime, of which the
+    /// total score should be minimized.
+    pub fn minimize_from_labels<L: Into<Label>>(scores: Vec<(L, Number)>) -> SimpleFitness {
+        SimpleFitness { score_card: ScoreCard::minimize_from_labels(scores) }
+    }
 }
 
 impl Fitness for SimpleFitness {
     fn score_card(&self) -> &ScoreCard { &self.score_card }
 }
 
+impl FitnessMut for SimpleFitness {
+    fn score_card_mut(&mut self) -> &mut ScoreCard { &mut self.score_card }
+}
+
+impl FromScoreCard for SimpleFitness {
+    fn from_score_card(score_card: ScoreCard) -> SimpleFitness {
+        SimpleFitness { score_card: score_card }
+    }
+}
+
+/// Label of a score.
+///
+/// Either a static string, or a string that is only known at runtime, such as
+/// the name of a test case.
+pub type Label = Cow<'static, str>;
+
 pub type Score = (&'static str, Number);
 
 /// List of tuples of a score label and a score value.
 pub type Scores = Vec<Score>;
 
+pub type LabeledScore = (Label, Number);
+
+/// List of tuples of a score label and a score value, where labels can be made at runtime.
+pub type LabeledScores = Vec<LabeledScore>;
+
+/// Whether higher or lower scores are better.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,RustcEncodable)]
+pub enum Direction {
+    Maximize,
+    Minimize
+}
+
 /// Immutable tagged list of scores.
 ///
 /// The final score of an individual can be composed of many labeled subscores
-/// (for manual evaluation later on). Pass the scoresas a list of tuples with a
-/// static string and a score. The final score is the total of all subscores.
+/// (for manual evaluation later on). Pass the scores as a list of tuples with a
+/// static string and a score, or use `from_labels()` for labels that are made
+/// at runtime. The final score is the total of all subscores.
+///
+/// By default, higher scores are better. Create the ScoreCard with
+/// `minimize()` if lower scores are better, or use `with_direction()` to
+/// minimize individual subscores, such as an error term, while maximizing the
+/// others. Scores are always logged as-is; the direction is only used when
+/// comparing ScoreCards.
 ///
 /// Example:
 ///
 /// ```
 /// # use moonlander_gp::ScoreCard;
+/// # use moonlander_gp::genetic::Direction;
 /// let score = ScoreCard::new(vec![
 ///     ("food", 10.0),
 ///     ("thirst", -5.0)
 /// ]);
+///
+/// let error = ScoreCard::minimize(vec![
+///     ("squared_error", 0.3)
+/// ]);
+///
+/// let mixed = ScoreCard::new(vec![
+///     ("food", 10.0),
+///     ("steps", 120.0)
+/// ]).with_direction("steps", Direction::Minimize);
+///
+/// let per_case = ScoreCard::from_labels((0..3).map(|i| {
+///     (format!("case_{}", i), 1.0)
+/// }).collect());
 /// ```
-#[derive(Clone,RustcEncodable)]
-pub struct ScoreCard(Scores, Number);
+///
+/// A ScoreCard is serialized as a pair of the list of subscores and the total
+/// score, such as `[[["food",10.0],["thirst",-5.0]],5.0]` in JSON. Directions
+/// are not serialized.
+#[derive(Clone)]
+pub struct ScoreCard {
+    scores: LabeledScores,
+    total: Number,
+
+    /// Direction of all subscores that don't have their own direction
+    direction: Direction,
+
+    /// Subscores with their own direction
+    directions: Vec<(Label, Direction)>,
+
+    /// Value to compare ScoreCards by, for which higher is always better
+    objective: Number,
+
+    /// Whether this is the score of a program that couldn't be evaluated
+    failed: bool
+}
 
 impl ScoreCard {
     pub fn new(scores: Scores) -> ScoreCard {
-        let sum = scores.iter().map(|&(_, x)| x).fold(0.0, Add::add);
-        ScoreCard(scores, sum)
+        ScoreCard::from_labels(scores)
+    }
+
+    /// Create a ScoreCard of which the total score should be minimized.
+    pub fn minimize(scores: Scores) -> ScoreCard {
+        ScoreCard::minimize_from_labels(scores)
     }
 
-    pub fn add(self, scores: Scores) -> ScoreCard {
-        let mut xs = self.0;
-        xs.extend(scores);
-        ScoreCard::new(xs)
+    /// Create a ScoreCard with labels that can be made at runtime, such as
+    /// Strings.
+    pub fn from_labels<L: Into<Label>>(scores: Vec<(L, Number)>) -> ScoreCard {
+        ScoreCard::from_parts(to_scores(scores), Direction::Maximize, vec![])
     }
 
-    pub fn scores(&self) -> &Scores {
-        &self.0
+    /// Create a ScoreCard with labels that can be made at runtime, of which
+    /// the total score should be minimized.
+    pub fn minimize_from_labels<L: Into<Label>>(scores: Vec<(L, Number)>) -> ScoreCard {
+        ScoreCard::from_parts(to_scores(scores), Direction::Minimize, vec![])
     }
 
+    /// The worst possible score, for a program that couldn't be evaluated.
+    ///
+    /// It loses from every other score, also after aggregating. Its total
+    /// score is the worst possible total in the given direction, and it is
+    /// left out of the statistics of a population.
+    pub fn worst(direction: Direction) -> ScoreCard {
+        let mut card = ScoreCard::from_parts(vec![], direction, vec![]);
+        card.failed = true;
+        card.update_totals();
+        card
+    }
+
+    fn from_parts(scores: LabeledScores, direction: Direction, directions: Vec<(Label, Direction)>) -> ScoreCard {
+        let mut card = ScoreCard {
+            scores: scores,
+            total: 0.0,
+            direction: direction,
+            directions: directions,
+            objective: 0.0,
+            failed: false
+        };
+        card.update_totals();
+        card
+    }
+
+    fn update_totals(&mut self) {
+        self.total = self.scores.iter().map(|&(_, x)| x).fold(0.0, Add::add);
+        self.objective = self.scores.iter()
+            .map(|&(ref name, x)| match self.direction_of(name) {
+                Direction::Maximize => x,
+                Direction::Minimize => -x
+            })
+            .fold(0.0, Add::add);
+
+        if self.failed {
+            self.total = match self.direction {
+                Direction::Maximize => Number::NEG_INFINITY,
+                Direction::Minimize => Number::INFINITY
+            };
+            self.objective = Number::NEG_INFINITY;
+        }
+    }
+
+    /// Return a copy of this ScoreCard, with its own direction for the given subscore.
+    pub fn with_direction<L: Into<Label>>(mut self, name: L, direction: Direction) -> ScoreCard {
+        let name = name.into();
+        self.directions.retain(|&(ref n, _)| *n != name);
+        self.directions.push((name, direction));
+        self.update_totals();
+        self
+    }
+
+    /// Return a copy of this ScoreCard with the given subscore replaced, or
+    /// added if there is no subscore with that name yet.
+    pub fn with_score<L: Into<Label>>(mut self, name: L, value: Number) -> ScoreCard {
+        let name = name.into();
+        match find_rec(&mut self.scores, &name) {
+            Some(rec) => rec.1 = value,
+            None => self.scores.push((name, value))
+        }
+        self.update_totals();
+        self
+    }
+
+    pub fn add(mut self, scores: Scores) -> ScoreCard {
+        self.scores.extend(to_scores(scores));
+        self.update_totals();
+        self
+    }
+
+    pub fn scores(&self) -> &LabeledScores {
+        &self.scores
+    }
+
+    /// The value of the subscore with the given name, if there is one.
+    pub fn get(&self, name: &str) -> Option<Number> {
+        self.scores.iter().find(|&&(ref n, _)| n == name).map(|&(_, x)| x)
+    }
+
+    /// Sum of all subscores.
     pub fn total_score(&self) -> Number {
-        self.1
+        self.total
+    }
+
+    /// The direction of the total score.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The direction of the subscore with the given name.
+    pub fn direction_of(&self, name: &str) -> Direction {
+        self.directions.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map_or(self.direction, |&(_, d)| d)
+    }
+
+    /// The value that ScoreCards are compared by, for which higher is always better.
+    ///
+    /// This is the total score with every minimized subscore negated.
+    pub fn objective(&self) -> Number {
+        self.objective
+    }
+
+    /// Compare this ScoreCard by an aggregation of its subscores, instead of their sum.
+    ///
+    /// The subscores and total score are unchanged. Adding or dividing the
+    /// ScoreCard afterwards goes back to comparing by the sum.
+    pub fn aggregate(&mut self, aggregation: &Aggregation, ranges: &ScoreRanges) {
+        if self.failed { return; }
+        self.objective = aggregation.objective(self, ranges);
+    }
+
+    /// Share the objective with the programs in the same niche.
+    ///
+    /// A positive objective is divided by the niche count, and a negative
+    /// objective is multiplied by it, so that crowded programs always lose.
+    /// Like `aggregate()`, this only changes how ScoreCards are compared.
+    pub fn share(&mut self, niche_count: Number) {
+        if self.objective >= 0.0 {
+            self.objective /= niche_count;
+        } else {
+            self.objective *= niche_count;
+        }
+    }
+
+    /// Blend the objective with the novelty of the program.
+    ///
+    /// The new objective is `weight * novelty + (1 - weight) * objective`, so
+    /// a weight of 1 gives pure novelty search. Like `aggregate()`, this only
+    /// changes how ScoreCards are compared.
+    pub fn reward_novelty(&mut self, novelty: Number, weight: Number) {
+        if self.failed { return; }
+        self.objective = weight * novelty + (1.0 - weight) * self.objective;
+    }
+
+    /// Whether this is the score of a program that couldn't be evaluated.
+    pub fn is_failed(&self) -> bool {
+        self.failed
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.len() == 0
+        self.scores.len() == 0
+    }
+}
+
+impl Encodable for ScoreCard {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_tuple_struct("ScoreCard", 2, |s| {
+            s.emit_tuple_struct_arg(0, |s| self.scores.encode(s))?;
+            s.emit_tuple_struct_arg(1, |s| self.total.encode(s))
+        })
     }
 }
 
 impl PartialEq for ScoreCard {
     fn eq(&self, other: &Self) -> bool {
-        return self.1.eq(&other.1);
+        return self.objective.eq(&other.objective);
     }
 }
 
@@ -88,21 +344,25 @@ impl Eq for ScoreCard {
 
 impl PartialOrd for ScoreCard {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        return self.1.partial_cmp(&other.1);
+        return self.objective.partial_cmp(&other.objective);
     }
 }
 
 impl Ord for ScoreCard {
     fn cmp(&self, other: &Self) -> Ordering {
-        if Number::is_nan(self.1) && Number::is_nan(other.1) { return Ordering::Equal; }
-        if Number::is_nan(self.1) { return Ordering::Less; }
-        if Number::is_nan(other.1) { return Ordering::Greater; }
+        if Number::is_nan(self.objective) && Number::is_nan(other.objective) { return Ordering::Equal; }
+        if Number::is_nan(self.objective) { return Ordering::Less; }
+        if Number::is_nan(other.objective) { return Ordering::Greater; }
 
-        return self.1.partial_cmp(&other.1).unwrap();
+        return self.objective.partial_cmp(&other.objective).unwrap();
     }
 }
 
-fn find_rec<'a>(scores: &'a mut Scores, name: &'static str) -> Option<&'a mut Score> {
+fn to_scores<L: Into<Label>>(scores: Vec<(L, Number)>) -> LabeledScores {
+    scores.into_iter().map(|(name, x)| (name.into(), x)).collect()
+}
+
+fn find_rec<'a>(scores: &'a mut LabeledScores, name: &str) -> Option<&'a mut LabeledScore> {
     for x in scores.iter_mut() {
         if x.0 == name {
             return Some(x);
@@ -122,28 +382,42 @@ impl Add<ScoreCard> for ScoreCard {
 
 impl <'a> ::std::ops::AddAssign<&'a ScoreCard> for ScoreCard {
     fn add_assign(&mut self, rhs: &'a ScoreCard) {
-        for &(name, value) in rhs.0.iter() {
+        // An empty ScoreCard (such as the start of a sum) takes on the directions of the other one
+        if self.is_empty() && self.directions.is_empty() {
+            self.direction = rhs.direction;
+            self.directions = rhs.directions.clone();
+        }
+
+        for &(ref name, value) in rhs.scores.iter() {
             let mut increased = false;
-            match find_rec(&mut self.0, name) {
+            match find_rec(&mut self.scores, name) {
                 Some(rec) => { rec.1 += value; increased = true; },
                 None => { /* Moved outside match because borrow checker can't end scope early */ }
             }
             if !increased {
-                self.0.push((name, value));
+                self.scores.push((name.clone(), value));
+            }
+
+            // Subscores keep their direction when they're merged into this ScoreCard
+            let direction = rhs.direction_of(name);
+            if self.direction_of(name) != direction {
+                self.directions.push((name.clone(), direction));
             }
-            self.1 += value;
         }
+        self.failed |= rhs.failed;
+        self.update_totals();
     }
 }
 
 impl ::std::ops::Div<Number> for ScoreCard {
     type Output = ScoreCard;
 
-    fn div(self, rhs: Number) -> Self::Output {
-        ScoreCard(
-            self.0.into_iter().map(|(n, v)| (n, v / rhs)).collect(),
-            self.1 / rhs
-        )
+    fn div(mut self, rhs: Number) -> Self::Output {
+        for score in self.scores.iter_mut() {
+            score.1 /= rhs;
+        }
+        self.update_totals();
+        self
     }
 }
 
@@ -158,7 +432,8 @@ mod tests {
 
         let added = one + two;
 
-        assert_eq!(vec![("a", 2.0)], added.0);
+        assert_eq!(Some(2.0), added.get("a"));
+        assert_eq!(1, added.scores().len());
         assert_eq!(2.0, added.total_score());
     }
 
@@ -169,7 +444,60 @@ mod tests {
 
         let added = one + two;
 
-        assert_eq!(vec![("a", 1.0),("b", 1.0)], added.0);
+        assert_eq!(Some(1.0), added.get("a"));
+        assert_eq!(Some(1.0), added.get("b"));
+        assert_eq!(2, added.scores().len());
         assert_eq!(2.0, added.total_score());
     }
+
+    #[test]
+    fn minimize_scorecards() {
+        let small = ScoreCard::minimize(vec![("error", 1.0)]);
+        let large = ScoreCard::minimize(vec![("error", 2.0)]);
+
+        assert!(small > large);
+        assert_eq!(1.0, small.total_score());
+    }
+
+    #[test]
+    fn minimize_single_score() {
+        let fast = ScoreCard::new(vec![("food", 10.0), ("steps", 5.0)]).with_direction("steps", Direction::Minimize);
+        let slow = ScoreCard::new(vec![("food", 10.0), ("steps", 8.0)]).with_direction("steps", Direction::Minimize);
+
+        assert!(fast > slow);
+        assert_eq!(5.0, fast.objective());
+    }
+
+    #[test]
+    fn add_scorecards_keeps_directions() {
+        let error = ScoreCard::minimize(vec![("error", 2.0)]);
+        let added = ScoreCard::new(vec![("food", 3.0)]) + error.clone();
+
+        assert_eq!(Direction::Minimize, added.direction_of("error"));
+        assert_eq!(1.0, added.objective());
+        assert_eq!(Direction::Minimize, (ScoreCard::new(vec![]) + error).direction());
+    }
+
+    #[test]
+    fn add_scorecards_with_runtime_labels() {
+        let one = ScoreCard::from_labels(vec![(format!("case_{}", 1), 1.0), (format!("case_{}", 2), 1.0)]);
+        let two = ScoreCard::new(vec![("case_1", 2.0)]);
+
+        let added = one + two;
+
+        assert_eq!(Some(3.0), added.get("case_1"));
+        assert_eq!(Some(1.0), added.get("case_2"));
+        assert_eq!(4.0, added.total_score());
+    }
+
+    #[test]
+    fn encode_as_scores_and_total() {
+        let card = ScoreCard::new(vec![("food", 10.0), ("steps", 4.0)]).with_direction("steps", Direction::Minimize);
+        let encoded = ::rustc_serialize::json::encode(&card).unwrap();
+        assert_eq!(r#"[[["food",10.0],["steps",4.0]],14.0]"#, encoded);
+
+        let decoded: (Vec<(String, Number)>, Number) = ::rustc_serialize::json::decode(&encoded).unwrap();
+        assert_eq!(card.total_score(), decoded.1);
+        assert_eq!(card.scores().iter().map(|&(ref n, x)| (n.to_string(), x)).collect::<Vec<_>>(), decoded.0);
+    }
 }
@@ -0,0 +1,298 @@
+use std::rc::Rc;
+use std::usize;
+use rand::Rng;
+use super::super::{AstNode, Number};
+use super::super::ast::{NodeInTree, find_nodes_and_parents, replace_to_root, depth, size, level};
+
+/// What to do with offspring that exceed the tree limits.
+#[derive(Clone,Copy,Debug,PartialEq,RustcEncodable)]
+pub enum LimitPolicy {
+    /// Discard the offspring and apply the operator again, at most the given
+    /// number of times. If the offspring still doesn't fit, the parent is
+    /// returned instead.
+    Retry(u32),
+
+    /// Return a copy of the parent instead of the offspring.
+    ReturnParent,
+
+    /// Shrink the offspring by replacing oversized subtrees with their smallest
+    /// descendant of the same type. If that doesn't make it fit, the parent is
+    /// returned instead.
+    Trim
+}
+
+/// Hard limits on the depth and size of programs.
+///
+/// These limits are enforced on the offspring of every genetic operation done
+/// by `evolve`, so that programs can't grow without bounds.
+#[derive(Clone,Copy,Debug,RustcEncodable)]
+pub struct TreeLimits {
+    /// Maximum depth of a program
+    pub max_depth: usize,
+
+    /// Maximum number of nodes in a program
+    pub max_size: usize,
+
+    /// What to do with offspring that are too large
+    pub policy: LimitPolicy
+}
+
+/// Statistics on how often the tree limits were hit.
+#[derive(Clone,Copy,Debug,Default,RustcEncodable)]
+pub struct LimitStats {
+    /// Number of offspring produced by the genetic operators
+    pub offspring: usize,
+
+    /// Number of offspring that exceeded the limits
+    pub violations: usize,
+
+    /// Number of times an operator was applied again
+    pub retries: usize,
+
+    /// Number of offspring that were trimmed to fit
+    pub trimmed: usize,
+
+    /// Number of offspring that were replaced by their parent
+    pub parents_returned: usize
+}
+
+impl LimitStats {
+    /// Fraction of offspring that exceeded the limits.
+    pub fn hit_rate(&self) -> Number {
+        if self.offspring == 0 { return 0.0; }
+        self.violations as Number / self.offspring as Number
+    }
+}
+
+impl TreeLimits {
+    pub fn new(max_depth: usize, max_size: usize, policy: LimitPolicy) -> TreeLimits {
+        TreeLimits { max_depth: max_depth, max_size: max_size, policy: policy }
+    }
+
+    /// Limits that allow programs of any size.
+    pub fn unlimited() -> TreeLimits {
+        TreeLimits::new(usize::MAX, usize::MAX, LimitPolicy::ReturnParent)
+    }
+
+    /// Whether the given tree fits within the limits.
+    pub fn allows(&self, tree: &AstNode) -> bool {
+        depth(tree) <= self.max_depth && size(tree) <= self.max_size
+    }
+
+    /// Apply a genetic operator that produces one child from one parent.
+    ///
+    /// The child is checked against the limits and handled according to the
+    /// policy. `operator` is only called again when the policy is `Retry`.
+    pub fn apply<T, R, O>(&self, parent: &T, stats: &mut LimitStats, rng: &mut R, mut operator: O) -> Box<T>
+        where T: AstNode+Clone,
+              R: Rng,
+              O: FnMut(&mut R) -> Box<T>
+    {
+        let mut child = operator(rng);
+        let mut attempt = 0;
+        loop {
+            stats.offspring += 1;
+            if self.allows(child.as_ref()) {
+                return child;
+            }
+            stats.violations += 1;
+
+            match self.policy {
+                LimitPolicy::Retry(n) if attempt < n => {
+                    attempt += 1;
+                    stats.retries += 1;
+                    child = operator(rng);
+                },
+                _ => return self.fallback(child, parent, stats).0
+            }
+        }
+    }
+
+    /// Apply a genetic operator that produces two children from two parents.
+    ///
+    /// Like `apply`, but for operators like crossover. When falling back to a
+    /// parent, the first child is replaced by the first parent and the second
+    /// child by the second parent.
+    pub fn apply_pair<T, R, O>(&self, parent1: &T, parent2: &T, stats: &mut LimitStats, rng: &mut R, operator: O) -> (Box<T>, Box<T>)
+        where T: AstNode+Clone,
+              R: Rng,
+              O: FnMut(&mut R) -> (Box<T>, Box<T>)
+    {
+        self.apply_pair_traced(parent1, parent2, stats, rng, operator).0
+    }
+
+    /// Like `apply_pair`, but also report for each child whether it was
+    /// replaced by a copy of its parent.
+    pub fn apply_pair_traced<T, R, O>(&self, parent1: &T, parent2: &T, stats: &mut LimitStats, rng: &mut R, mut operator: O) -> ((Box<T>, Box<T>), (bool, bool))
+        where T: AstNode+Clone,
+              R: Rng,
+              O: FnMut(&mut R) -> (Box<T>, Box<T>)
+    {
+        let (mut child1, mut child2) = operator(rng);
+        let mut attempt = 0;
+        loop {
+            stats.offspring += 2;
+            let fits1 = self.allows(child1.as_ref());
+            let fits2 = self.allows(child2.as_ref());
+            if fits1 && fits2 {
+                return ((child1, child2), (false, false));
+            }
+            if !fits1 { stats.violations += 1; }
+            if !fits2 { stats.violations += 1; }
+
+            match self.policy {
+                LimitPolicy::Retry(n) if attempt < n => {
+                    attempt += 1;
+                    stats.retries += 1;
+                    let (new1, new2) = operator(rng);
+                    child1 = new1;
+                    child2 = new2;
+                },
+                _ => {
+                    let (child1, returned1) = if fits1 { (child1, false) } else { self.fallback(child1, parent1, stats) };
+                    let (child2, returned2) = if fits2 { (child2, false) } else { self.fallback(child2, parent2, stats) };
+                    return ((child1, child2), (returned1, returned2));
+                }
+            }
+        }
+    }
+
+    /// Shrink a tree until it fits within the limits.
+    ///
+    /// Repeatedly replaces the largest offending subtree with its smallest
+    /// descendant of the same node type. Returns `None` if the tree can't be
+    /// made to fit this way.
+    pub fn trim<T: AstNode+Clone>(&self, tree: Box<T>) -> Option<Box<T>> {
+        let mut tree = tree;
+        while !self.allows(tree.as_ref()) {
+            tree = match self.shrink(tree.as_ref()) {
+                Some(smaller) => smaller,
+                None => return None
+            };
+        }
+        Some(tree)
+    }
+
+    /// Trim or replace a child that doesn't fit, returning whether it was
+    /// replaced by its parent.
+    fn fallback<T: AstNode+Clone>(&self, child: Box<T>, parent: &T, stats: &mut LimitStats) -> (Box<T>, bool) {
+        if let LimitPolicy::Trim = self.policy {
+            if let Some(trimmed) = self.trim(child) {
+                stats.trimmed += 1;
+                return (trimmed, false);
+            }
+        }
+        stats.parents_returned += 1;
+        (Box::new(parent.clone()), true)
+    }
+
+    /// Do a single shrinking step, hoisting a descendant into the place of the
+    /// largest subtree that contributes to the tree being too large.
+    fn shrink<T: AstNode+Clone>(&self, tree: &T) -> Option<Box<T>> {
+        let too_deep = depth(tree) > self.max_depth;
+        let naps = find_nodes_and_parents(tree);
+
+        let mut best: Option<(usize, &Rc<NodeInTree>, &AstNode)> = None;
+        for nap in naps.iter().skip(1) {
+            if too_deep && level(nap) + depth(nap.node) - 1 <= self.max_depth {
+                continue;
+            }
+            let node_size = size(nap.node);
+            if best.as_ref().map_or(false, |&(s, _, _)| s >= node_size) {
+                continue;
+            }
+            if let Some(replacement) = smallest_descendant_of_same_type(nap.node) {
+                best = Some((node_size, nap, replacement));
+            }
+        }
+
+        best.map(|(_, nap, replacement)| replace_to_root(nap, replacement.copy()))
+    }
+}
+
+fn smallest_descendant_of_same_type(node: &AstNode) -> Option<&AstNode> {
+    find_nodes_and_parents(node).into_iter()
+        .skip(1)
+        .map(|nap| nap.node)
+        .filter(|n| n.node_type() == node.node_type())
+        .min_by_key(|n| size(*n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::{depth, size};
+    use super::super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum List {
+        Cons(Box<List>),
+        Nil
+    }
+
+    impl_astnode!(List, 0,
+                  int Cons(next),
+                  leaf Nil());
+
+    fn list(n: usize) -> List {
+        (0..n).fold(List::Nil, |tail, _| List::Cons(Box::new(tail)))
+    }
+
+    #[test]
+    fn test_return_parent() {
+        let limits = TreeLimits::new(3, 10, LimitPolicy::ReturnParent);
+        let mut stats = LimitStats::default();
+        let mut rng = seeded_rng(1);
+
+        let parent = list(1);
+        let child = limits.apply(&parent, &mut stats, &mut rng, |_| Box::new(list(5)));
+
+        assert_eq!(parent, *child);
+        assert_eq!(1, stats.violations);
+        assert_eq!(1, stats.parents_returned);
+    }
+
+    #[test]
+    fn test_pair_returns_one_parent() {
+        let limits = TreeLimits::new(3, 10, LimitPolicy::ReturnParent);
+        let mut stats = LimitStats::default();
+        let mut rng = seeded_rng(1);
+
+        let (parent1, parent2) = (list(1), list(2));
+        let ((child1, child2), returned) = limits.apply_pair_traced(&parent1, &parent2, &mut stats, &mut rng, |_| (Box::new(list(0)), Box::new(list(5))));
+
+        // Only the child that is too deep is replaced by its parent
+        assert_eq!((false, true), returned);
+        assert_eq!(list(0), *child1);
+        assert_eq!(parent2, *child2);
+        assert_eq!(1, stats.violations);
+        assert_eq!(1, stats.parents_returned);
+    }
+
+    #[test]
+    fn test_retry() {
+        let limits = TreeLimits::new(3, 10, LimitPolicy::Retry(5));
+        let mut stats = LimitStats::default();
+        let mut rng = seeded_rng(1);
+
+        let mut sizes = vec![7, 6, 2].into_iter();
+        let child = limits.apply(&list(0), &mut stats, &mut rng, |_| Box::new(list(sizes.next().unwrap())));
+
+        assert_eq!(list(2), *child);
+        assert_eq!(3, stats.offspring);
+        assert_eq!(2, stats.retries);
+    }
+
+    #[test]
+    fn test_trim() {
+        let limits = TreeLimits::new(4, 100, LimitPolicy::Trim);
+        let mut stats = LimitStats::default();
+        let mut rng = seeded_rng(1);
+
+        let child = limits.apply(&list(0), &mut stats, &mut rng, |_| Box::new(list(10)));
+
+        assert!(depth(child.as_ref()) <= 4);
+        assert!(size(child.as_ref()) > 1);
+        assert_eq!(1, stats.trimmed);
+    }
+}
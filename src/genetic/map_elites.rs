@@ -0,0 +1,349 @@
+use super::{Fitness, LimitStats};
+use super::evolve::Weights;
+use super::crossover::crossover_tree;
+use super::mutate::mutate_tree;
+use super::super::{AstNode, Mutatable, Number, Population};
+use super::super::num::sum;
+use super::super::seed::derive_rng;
+use rand::Rng;
+use rayon::prelude::*;
+use rustc_serialize::Encodable;
+use rustc_serialize::json;
+use std::collections::BTreeMap;
+
+/// Position in the grid of a MAP-Elites archive, one bin per feature.
+pub type Cell = Vec<usize>;
+
+/// The best program found for a cell.
+#[derive(Clone,RustcEncodable)]
+pub struct Elite<P, F> {
+    pub cell: Cell,
+
+    /// Features of the program, as returned by the feature descriptor
+    pub features: Vec<Number>,
+
+    pub program: P,
+    pub fitness: F,
+
+    /// Iteration in which the program was found
+    pub generation: u32
+}
+
+/// MAP-Elites quality-diversity archive (Mouret & Clune, 2015).
+///
+/// Instead of a single champion, MAP-Elites looks for the best program of
+/// every kind. A feature descriptor maps every program to a vector of
+/// features, such as its size or how often it turns, and every feature range
+/// is divided into bins. The archive keeps the best program for every cell of
+/// the resulting grid. New candidates are made by mutating and crossing over
+/// elites picked at random.
+///
+/// Example:
+///
+/// ```ignore
+/// let descriptor = |p: &Program, _: &SimpleFitness| vec![size(p) as Number, turns(p) as Number];
+/// let mut archive = MapElites::new(vec![(0.0, 100.0), (0.0, 20.0)], vec![10, 10]);
+/// pop.score(fitness, &mut rng);
+/// archive.add_population(pop, &descriptor);
+/// for _ in 0..iterations {
+///     archive.step(100, &weights, fitness, &descriptor, &mut rng);
+/// }
+/// println!("{}", archive.to_json().unwrap());
+/// ```
+pub struct MapElites<P, F> {
+    /// Lower and upper bound of every feature. Features outside of the bounds
+    /// go into the first or last bin.
+    pub ranges: Vec<(Number, Number)>,
+
+    /// Number of bins for every feature
+    pub bins: Vec<usize>,
+
+    /// Number of calls to `step()` so far
+    pub generation: u32,
+
+    /// How often the tree limits were hit while making candidates
+    pub limit_stats: LimitStats,
+
+    elites: BTreeMap<Cell, Elite<P, F>>
+}
+
+impl <P, F> MapElites<P, F>
+    where P: AstNode+Clone+Mutatable+Sync,
+          F: Fitness+Send
+{
+    pub fn new(ranges: Vec<(Number, Number)>, bins: Vec<usize>) -> MapElites<P, F> {
+        assert_eq!(ranges.len(), bins.len(), "Need a number of bins for every feature range");
+        MapElites {
+            ranges: ranges,
+            bins: bins,
+            generation: 0,
+            limit_stats: LimitStats::default(),
+            elites: BTreeMap::new()
+        }
+    }
+
+    /// Return the cell for the given features.
+    pub fn cell_of(&self, features: &[Number]) -> Cell {
+        self.ranges.iter().zip(self.bins.iter()).enumerate().map(|(i, (&(lower, upper), &bins))| {
+            let x = features.get(i).cloned().unwrap_or(lower);
+            let fraction = if upper > lower { (x - lower) / (upper - lower) } else { 0.0 };
+            let bin = (fraction * bins as Number).floor();
+            if bin < 0.0 || bin.is_nan() { 0 } else { (bin as usize).min(bins.saturating_sub(1)) }
+        }).collect()
+    }
+
+    /// Consider a scored program for the archive.
+    ///
+    /// The program becomes the elite of its cell if the cell is empty, or if it
+    /// is better than the current elite. Returns whether it was added.
+    pub fn offer(&mut self, program: P, fitness: F, features: Vec<Number>) -> bool {
+        let cell = self.cell_of(&features);
+        if let Some(elite) = self.elites.get(&cell) {
+            if fitness.score_card() <= elite.fitness.score_card() {
+                return false;
+            }
+        }
+        self.elites.insert(cell.clone(), Elite {
+            cell: cell,
+            features: features,
+            program: program,
+            fitness: fitness,
+            generation: self.generation
+        });
+        true
+    }
+
+    /// Offer all programs of a scored population, such as a random initial population.
+    ///
+    /// Returns the number of programs that were added.
+    pub fn add_population<D>(&mut self, pop: Population<P, F>, descriptor: D) -> usize
+        where D: Fn(&P, &F) -> Vec<Number>
+    {
+        let mut added = 0;
+        for (program, fitness) in pop.population.into_iter().zip(pop.scores) {
+            let features = descriptor(&program, &fitness);
+            if self.offer(program, fitness, features) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Make, score and offer `batch` new candidates.
+    ///
+    /// Every candidate is made from elites picked at random, by mutation or
+    /// crossover in the ratio of `weights`, within `weights.limits`. Copies of
+    /// elites can't improve the archive, so `weights.reproduce` is ignored.
+    /// Candidates are scored in parallel, each with a random number generator
+    /// derived from a seed drawn from `rng`. Returns the number of candidates
+    /// that were added.
+    ///
+    /// Panics if both `weights.mutate` and `weights.crossover` are 0.
+    pub fn step<S, D, R>(&mut self, batch: usize, weights: &Weights, scoring_fn: S, descriptor: D, rng: &mut R) -> usize
+        where S: Fn(&P, &mut Rng) -> F + Sync,
+              D: Fn(&P, &F) -> Vec<Number>,
+              R: Rng
+    {
+        assert!(weights.mutate + weights.crossover > 0, "MAP-Elites needs a mutate or crossover weight above 0 to make candidates");
+        if self.elites.is_empty() { return 0; }
+        self.generation += 1;
+
+        let mut candidates = Vec::with_capacity(batch + 1);
+        {
+            let elites = self.elites.values().collect::<Vec<_>>();
+            while candidates.len() < batch {
+                pick![rng,
+                    weights.mutate, {
+                        let parent = &rng.choose(&elites).unwrap().program;
+                        let mutation = weights.limits.apply(parent, &mut self.limit_stats, rng, |rng| {
+                            let target_height = rng.next_u32() as i32 % weights.tree_height;
+                            mutate_tree(parent, target_height, rng)
+                        });
+                        candidates.push(*mutation);
+                    },
+                    weights.crossover, {
+                        let one = &rng.choose(&elites).unwrap().program;
+                        let two = &rng.choose(&elites).unwrap().program;
+                        let (child1, child2) = weights.limits.apply_pair(one, two, &mut self.limit_stats, rng, |rng| crossover_tree(one, two, rng));
+                        candidates.push(*child1);
+                        candidates.push(*child2);
+                    }
+                ];
+            }
+        }
+        candidates.truncate(batch);
+
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        let mut scores = Vec::with_capacity(candidates.len());
+        candidates.par_iter().enumerate().weight_max()
+            .map(|(i, candidate)| scoring_fn(candidate, &mut derive_rng(seed, generation, i)))
+            .collect_into(&mut scores);
+
+        let mut added = 0;
+        for (program, fitness) in candidates.into_iter().zip(scores) {
+            let features = descriptor(&program, &fitness);
+            if self.offer(program, fitness, features) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    pub fn get(&self, cell: &[usize]) -> Option<&Elite<P, F>> {
+        self.elites.get(cell)
+    }
+
+    /// All elites, ordered by cell.
+    pub fn elites(&self) -> Vec<&Elite<P, F>> {
+        self.elites.values().collect()
+    }
+
+    /// The best elite of all cells.
+    pub fn best(&self) -> Option<&Elite<P, F>> {
+        self.elites.values().max_by_key(|e| e.fitness.score_card())
+    }
+
+    /// Fraction of the cells that have an elite.
+    pub fn coverage(&self) -> Number {
+        let cells = self.bins.iter().fold(1, |a, b| a * b);
+        if cells == 0 { 0.0 } else { self.elites.len() as Number / cells as Number }
+    }
+
+    /// Sum of the total scores of all elites, a common measure of both
+    /// quality and diversity.
+    ///
+    /// Elites with a negative total score lower the QD score, so filling a
+    /// cell can make it go down. If scores can be negative, offset them so
+    /// that every possible score is positive before comparing QD scores.
+    pub fn qd_score(&self) -> Number {
+        sum(self.elites.values().map(|e| e.fitness.score_card().total_score()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.elites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elites.is_empty()
+    }
+
+    /// Export the archive as JSON.
+    ///
+    /// The result contains the feature ranges and bins of the grid, and every
+    /// elite with its cell, features, program and fitness.
+    pub fn to_json(&self) -> Result<String, json::EncoderError>
+        where P: Encodable,
+              F: Encodable
+    {
+        json::encode(&Export {
+            ranges: &self.ranges,
+            bins: &self.bins,
+            generation: self.generation,
+            coverage: self.coverage(),
+            elites: self.elites()
+        })
+    }
+}
+
+/// Serialized form of a MAP-Elites archive.
+#[derive(RustcEncodable)]
+struct Export<'a, P: 'a, F: 'a> {
+    ranges: &'a Vec<(Number, Number)>,
+    bins: &'a Vec<usize>,
+    generation: u32,
+    coverage: Number,
+    elites: Vec<&'a Elite<P, F>>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{SimpleFitness, TreeLimits};
+    use super::super::super::random_pop::random_population;
+    use super::super::super::seed::seeded_rng;
+    use super::super::super::{depth, size};
+
+    #[derive(Clone,PartialEq,Debug,RustcEncodable)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn score(tree: &Tree, _: &mut Rng) -> SimpleFitness {
+        SimpleFitness::new(vec![("size", size(tree) as Number)])
+    }
+
+    fn descriptor(tree: &Tree, _: &SimpleFitness) -> Vec<Number> {
+        vec![depth(tree) as Number]
+    }
+
+    #[test]
+    fn test_cell_of() {
+        let archive: MapElites<Tree, SimpleFitness> = MapElites::new(vec![(0.0, 10.0), (-1.0, 1.0)], vec![5, 2]);
+        assert_eq!(vec![0, 0], archive.cell_of(&[0.0, -1.0]));
+        assert_eq!(vec![2, 1], archive.cell_of(&[5.0, 0.5]));
+        assert_eq!(vec![4, 1], archive.cell_of(&[10.0, 3.0]));
+        assert_eq!(vec![0, 0], archive.cell_of(&[-3.0, -5.0]));
+    }
+
+    #[test]
+    fn test_keeps_best_per_cell() {
+        let mut archive = MapElites::new(vec![(0.0, 4.0)], vec![4]);
+        assert!(archive.offer(Tree::Leaf(1), SimpleFitness::new(vec![("score", 1.0)]), vec![0.5]));
+        assert!(!archive.offer(Tree::Leaf(2), SimpleFitness::new(vec![("score", 1.0)]), vec![0.7]));
+        assert!(archive.offer(Tree::Leaf(3), SimpleFitness::new(vec![("score", 2.0)]), vec![0.2]));
+        assert!(archive.offer(Tree::Leaf(4), SimpleFitness::new(vec![("score", 0.0)]), vec![3.5]));
+
+        assert_eq!(2, archive.len());
+        assert_eq!(Tree::Leaf(3), archive.get(&[0]).unwrap().program);
+        assert_eq!(0.5, archive.coverage());
+        assert_eq!(2.0, archive.qd_score());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut rng = seeded_rng(2);
+        let weights = Weights { reproduce: 0, mutate: 50, crossover: 50, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut archive = MapElites::new(vec![(1.0, 9.0)], vec![8]);
+
+        let mut pop: Population<Tree, SimpleFitness> = random_population(10, 2, &mut rng);
+        pop.score(score, &mut rng);
+        archive.add_population(pop, descriptor);
+        let coverage = archive.coverage();
+        let qd_score = archive.qd_score();
+
+        for _ in 0..10 {
+            archive.step(20, &weights, score, descriptor, &mut rng);
+        }
+        assert!(archive.coverage() > coverage);
+        assert!(archive.qd_score() > qd_score);
+        assert_eq!(10, archive.generation);
+    }
+
+    #[test]
+    #[should_panic(expected = "mutate or crossover weight")]
+    fn test_step_needs_operators() {
+        let weights = Weights { reproduce: 100, mutate: 0, crossover: 0, tree_height: 3, limits: TreeLimits::unlimited() };
+        let mut archive: MapElites<Tree, SimpleFitness> = MapElites::new(vec![(1.0, 9.0)], vec![8]);
+        archive.offer(Tree::Leaf(1), SimpleFitness::new(vec![("score", 1.0)]), vec![1.0]);
+        archive.step(20, &weights, score, descriptor, &mut seeded_rng(2));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut archive = MapElites::new(vec![(0.0, 4.0)], vec![4]);
+        archive.offer(Tree::Leaf(1), SimpleFitness::new(vec![("score", 1.0)]), vec![2.5]);
+
+        let decoded = json::Json::from_str(&archive.to_json().unwrap()).unwrap();
+        let elites = decoded.find("elites").and_then(|e| e.as_array()).unwrap();
+        assert_eq!(1, elites.len());
+        assert_eq!(Some(2), elites[0].find("cell").and_then(|c| c.as_array()).and_then(|c| c[0].as_u64()));
+        assert!(elites[0].find("program").is_some());
+        assert!(elites[0].find_path(&["fitness", "score_card"]).is_some());
+    }
+}
@@ -1,15 +1,42 @@
 //! Genetic operation functions
 mod mutate;
-pub use self::mutate::mutate_tree;
+pub use self::mutate::{mutate_tree, mutate_tree_traced};
 
 mod crossover;
-pub use self::crossover::crossover_tree;
+pub use self::crossover::{crossover_tree, crossover_tree_traced, crossover_tree_limited, size_fair_crossover, one_point_crossover, uniform_crossover};
+
+mod limits;
+pub use self::limits::{TreeLimits, LimitPolicy, LimitStats};
+
+mod equalize;
+pub use self::equalize::Equalizer;
+
+mod brood;
+pub use self::brood::brood_crossover;
 
 mod fitness;
-pub use self::fitness::{Fitness, SimpleFitness, ScoreCard, Scores};
+pub use self::fitness::{Fitness, FitnessMut, FromScoreCard, SimpleFitness, ScoreCard, Scores, LabeledScores, Label, Direction};
+
+mod aggregate;
+pub use self::aggregate::{Aggregation, Combine, ScoreRanges, score_ranges};
 
 mod select;
-pub use self::select::{tournament_selection};
+pub use self::select::{tournament_selection, lexicographic_tournament_selection, double_tournament_selection, Tarpeian};
 
 mod evolve;
-pub use self::evolve::{evolve, Weights};
+pub use self::evolve::{evolve, evolve_with, Weights};
+
+mod niching;
+pub use self::niching::{share_fitness, deterministic_crowding, structural_distance, phenotypic_distance};
+
+mod novelty;
+pub use self::novelty::{Behavior, NoveltyFitness, NoveltyArchive};
+
+mod alps;
+pub use self::alps::Alps;
+
+mod map_elites;
+pub use self::map_elites::{MapElites, Elite, Cell};
+
+mod coevolve;
+pub use self::coevolve::{Coevolution, OpponentSampling};
@@ -3,7 +3,7 @@
 /// Implementer should try to create persisted data-structures
 use rand;
 use super::super::AstNode;
-use super::super::ast::{replace_to_root, find_nodes_and_parents, NodeInTree};
+use super::super::ast::{replace_to_root, find_nodes_and_parents, level, position};
 
 
 /// Mutate a random node.
@@ -11,16 +11,17 @@ use super::super::ast::{replace_to_root, find_nodes_and_parents, NodeInTree};
 /// Pick a subtree at random, call `mutate()` on it, and return a new
 /// tree with the subtree replaced.
 pub fn mutate_tree<T: AstNode+Clone, R: rand::Rng+Sized>(ast: &T, target_height: i32, rng: &mut R) -> Box<T> {
+    mutate_tree_traced(ast, target_height, rng).0
+}
+
+/// Mutate a random node, and return the position of the mutated node.
+///
+/// Like `mutate_tree`, but also returns where the mutation took place, as the
+/// pre-order position of the node (see `position()`).
+pub fn mutate_tree_traced<T: AstNode+Clone, R: rand::Rng+Sized>(ast: &T, target_height: i32, rng: &mut R) -> (Box<T>, usize) {
     let naps = find_nodes_and_parents(ast);
     let picked = rng.choose(&naps).unwrap();
-    let height_diff = target_height - depth(picked);
+    let height_diff = target_height - level(picked) as i32;
     let mutated = picked.node.mutate(height_diff, rng);
-    replace_to_root(&picked, mutated)
-}
-
-fn depth<'a>(nap: &NodeInTree<'a>) -> i32 {
-    1 + match nap.root_path {
-        None => 0,
-        Some(ref parent) => depth(parent.as_ref())
-    }
+    (replace_to_root(&picked, mutated), position(picked))
 }
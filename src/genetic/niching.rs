@@ -0,0 +1,288 @@
+use super::{Fitness, FitnessMut, ScoreCard};
+use super::evolve::Weights;
+use super::crossover::crossover_tree_traced;
+use super::mutate::mutate_tree_traced;
+use super::super::{AstNode, Mutatable, Number, Population, Origin, Operator};
+use super::super::distance::tree_edit_distance;
+use super::super::seed::derive_rng;
+use rand::Rng;
+use rayon::prelude::*;
+use std::time::Instant;
+
+/// Distance between programs by the number of edits to turn one into the other.
+///
+/// Uses `tree_edit_distance`, which is slow for large programs. Sharing needs
+/// the distance between every pair of programs, so prefer `phenotypic_distance`
+/// for large populations.
+pub fn structural_distance<P: AstNode, F>(p1: &P, _: &F, p2: &P, _: &F) -> Number {
+    tree_edit_distance(p1, p2) as Number
+}
+
+/// Distance between programs by their scores.
+///
+/// The Euclidean distance between the subscores, where a subscore that only
+/// one of the programs has counts as 0 for the other.
+pub fn phenotypic_distance<P, F: Fitness>(_: &P, f1: &F, _: &P, f2: &F) -> Number {
+    score_distance(f1.score_card(), f2.score_card())
+}
+
+fn score_distance(s1: &ScoreCard, s2: &ScoreCard) -> Number {
+    let mut total = 0.0;
+    for &(ref name, x) in s1.scores().iter() {
+        total += (x - s2.get(name).unwrap_or(0.0)).powi(2);
+    }
+    for &(ref name, y) in s2.scores().iter() {
+        if s1.get(name).is_none() {
+            total += y.powi(2);
+        }
+    }
+    total.sqrt()
+}
+
+/// Divide the score of every program by the number of programs near it.
+///
+/// Fitness sharing (Goldberg & Richardson, 1987) keeps a population from
+/// collapsing onto a single solution, by making programs in crowded niches
+/// less attractive to select. Every program within `radius` of another counts
+/// as part of its niche, weighted by `1 - (distance / radius) ^ alpha`.
+///
+/// Call this after scoring, and before selecting with `tournament_selection`
+/// or any other selector. Like `Population::aggregate`, this only changes how
+/// programs are compared, not the scores themselves.
+pub fn share_fitness<P, F, D>(pop: &mut Population<P, F>, radius: Number, alpha: Number, distance: D)
+    where P: Clone+Sync,
+          F: FitnessMut+Sync,
+          D: Fn(&P, &F, &P, &F) -> Number + Sync
+{
+    let n = pop.n();
+    let mut niche_counts = Vec::with_capacity(n);
+    {
+        let programs = &pop.population;
+        let scores = &pop.scores;
+        (0..n).collect::<Vec<usize>>().par_iter().weight_max()
+            .map(|&i| {
+                let mut count = 0.0;
+                for j in 0..n {
+                    let d = if i == j { 0.0 } else { distance(&programs[i], &scores[i], &programs[j], &scores[j]) };
+                    if d < radius {
+                        count += 1.0 - (d / radius).powf(alpha);
+                    }
+                }
+                count
+            })
+            .collect_into(&mut niche_counts);
+    }
+
+    for (fitness, count) in pop.scores.iter_mut().zip(niche_counts) {
+        fitness.score_card_mut().share(count);
+    }
+}
+
+/// Produce and score a new generation by deterministic crowding.
+///
+/// An alternative to `evolve` that preserves niches (Mahfoud, 1992). Parents
+/// are paired up at random, and every pair produces two children by one of
+/// the operators in `weights`. Every child competes with the parent that is
+/// closest to it, and replaces that parent if it has a better score.
+///
+/// Since children are scored to compete with their parents, the new population
+/// is already scored and should not be scored again.
+pub fn deterministic_crowding<P, F, S, D, R>(pop: Population<P, F>, weights: &Weights, scoring_fn: S, distance: D, rng: &mut R) -> Population<P, F>
+    where P: AstNode+Clone+Mutatable+Sync,
+          F: Fitness+Send,
+          S: Fn(&P, &mut Rng) -> F + Sync,
+          D: Fn(&P, &F, &P, &F) -> Number,
+          R: Rng
+{
+    let start = Instant::now();
+    let mut ret = Population::new(pop.n(), pop.generation + 1);
+    ret.next_id = pop.next_id;
+
+    let mut order = (0..pop.n()).collect::<Vec<usize>>();
+    rng.shuffle(&mut order);
+    let pairs = order.chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect::<Vec<_>>();
+
+    // Make two children for every pair of parents, with their origins and ages
+    let mut children = Vec::with_capacity(2 * pairs.len());
+    let age = |parents: &[usize]| 1 + parents.iter().map(|&p| pop.ages.get(p).cloned().unwrap_or(0)).max().unwrap_or(0);
+    let origin = |operator, parents: &[usize], point| Origin::new(operator, parents.iter().filter_map(|&p| pop.ids.get(p).cloned()).collect(), point);
+    let reproduce = |p: usize| (pop.population[p].clone(), origin(Operator::Reproduce, &[p], None), age(&[p]));
+    for &(a, b) in pairs.iter() {
+        let (one, two) = (&pop.population[a], &pop.population[b]);
+
+        pick![rng,
+            weights.reproduce, {
+                children.push(reproduce(a));
+                children.push(reproduce(b));
+            },
+            weights.mutate, {
+                for &(p, parent) in [(a, one), (b, two)].iter() {
+                    let returned = ret.limit_stats.parents_returned;
+                    let mut point = None;
+                    let mutation = weights.limits.apply(parent, &mut ret.limit_stats, rng, |rng| {
+                        let target_height = rng.next_u32() as i32 % weights.tree_height;
+                        let (mutation, p) = mutate_tree_traced(parent, target_height, rng);
+                        point = Some(p);
+                        mutation
+                    });
+                    if ret.limit_stats.parents_returned > returned {
+                        children.push(reproduce(p));
+                    } else {
+                        children.push((*mutation, origin(Operator::Mutate, &[p], point), age(&[p])));
+                    }
+                }
+            },
+            weights.crossover, {
+                let mut points = None;
+                let ((child1, child2), (returned1, returned2)) = weights.limits.apply_pair_traced(one, two, &mut ret.limit_stats, rng, |rng| {
+                    let (children, p) = crossover_tree_traced(one, two, rng);
+                    points = Some(p);
+                    children
+                });
+                children.push(if returned1 { reproduce(a) } else { (*child1, origin(Operator::Crossover, &[a, b], points.map(|p| p.0)), age(&[a, b])) });
+                children.push(if returned2 { reproduce(b) } else { (*child2, origin(Operator::Crossover, &[b, a], points.map(|p| p.1)), age(&[a, b])) });
+            }
+        ];
+    }
+    ret.variation_time = start.elapsed();
+
+    let start = Instant::now();
+    let seed = rng.next_u64();
+    let generation = ret.generation;
+    let mut child_scores = Vec::with_capacity(children.len());
+    children.par_iter().enumerate().weight_max()
+        .map(|(i, &(ref child, _, _))| scoring_fn(child, &mut derive_rng(seed, generation, i)))
+        .collect_into(&mut child_scores);
+    ret.scoring_time = start.elapsed();
+
+    // Every child competes with the parent closest to it
+    let mut winners = Vec::with_capacity(pop.n());
+    for (k, &(a, b)) in pairs.iter().enumerate() {
+        let (c, d) = (2 * k, 2 * k + 1);
+        let parent_dist = |p: usize, c: usize| distance(&pop.population[p], &pop.scores[p], &children[c].0, &child_scores[c]);
+        let matches = if parent_dist(a, c) + parent_dist(b, d) <= parent_dist(a, d) + parent_dist(b, c) {
+            [(a, c), (b, d)]
+        } else {
+            [(a, d), (b, c)]
+        };
+        for &(p, c) in matches.iter() {
+            winners.push(if child_scores[c].score_card() > pop.scores[p].score_card() { Survivor::Child(c) } else { Survivor::Parent(p) });
+        }
+    }
+    if pop.n() % 2 == 1 {
+        winners.push(Survivor::Parent(order[pop.n() - 1]));
+    }
+
+    let mut parents = pop.population.into_iter().zip(pop.scores).map(Some).collect::<Vec<_>>();
+    let mut children = children.into_iter().zip(child_scores).map(Some).collect::<Vec<_>>();
+    for winner in winners {
+        match winner {
+            Survivor::Parent(p) => {
+                let (program, fitness) = parents[p].take().unwrap();
+                let origin = Origin::new(Operator::Reproduce, pop.ids.get(p).cloned().into_iter().collect(), None);
+                ret.add_with_origin(program, origin, 1 + pop.ages.get(p).cloned().unwrap_or(0));
+                ret.scores.push(fitness);
+            },
+            Survivor::Child(c) => {
+                let ((program, origin, age), fitness) = children[c].take().unwrap();
+                ret.add_with_origin(program, origin, age);
+                ret.scores.push(fitness);
+            }
+        }
+    }
+    ret
+}
+
+/// Which program of a parent and child goes to the next generation.
+enum Survivor {
+    Parent(usize),
+    Child(usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{SimpleFitness, TreeLimits, LimitPolicy};
+    use super::super::super::random_pop::random_population;
+    use super::super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn score(tree: &Tree, _: &mut Rng) -> SimpleFitness {
+        SimpleFitness::new(vec![("size", super::super::super::size(tree) as Number)])
+    }
+
+    #[test]
+    fn test_share_fitness() {
+        let mut pop: Population<Tree, SimpleFitness> = Population::new(3, 0);
+        for &(leaf, score) in [(1, 4.0), (1, 4.0), (2, 3.0)].iter() {
+            pop.add(Tree::Leaf(leaf));
+            pop.scores.push(SimpleFitness::new(vec![("score", score)]));
+        }
+
+        share_fitness(&mut pop, 0.5, 1.0, structural_distance);
+
+        // The two identical programs share a niche, and now lose from the unique one
+        assert_eq!(2.0, pop.scores[0].score_card().objective());
+        assert_eq!(4.0, pop.scores[0].score_card().total_score());
+        assert!(pop.scores[2].score_card() > pop.scores[0].score_card());
+    }
+
+    #[test]
+    fn test_phenotypic_distance() {
+        let one = SimpleFitness::new(vec![("a", 1.0), ("b", 4.0)]);
+        let two = SimpleFitness::new(vec![("a", 4.0), ("c", 0.0)]);
+        assert_eq!(5.0, phenotypic_distance(&(), &one, &(), &two));
+    }
+
+    #[test]
+    fn test_deterministic_crowding() {
+        let mut rng = seeded_rng(3);
+        let weights = Weights { reproduce: 10, mutate: 30, crossover: 60, tree_height: 3, limits: TreeLimits::unlimited() };
+
+        let mut pop: Population<Tree, SimpleFitness> = random_population(21, 3, &mut rng);
+        pop.score(score, &mut rng);
+        let best = pop.best_score();
+
+        for _ in 0..5 {
+            pop = deterministic_crowding(pop, &weights, score, structural_distance, &mut rng);
+            assert_eq!(21, pop.n());
+            assert_eq!(21, pop.scores.len());
+        }
+
+        // Parents are only ever replaced by better children
+        assert!(pop.best_score() >= best);
+        assert_eq!(5, pop.generation);
+    }
+
+    #[test]
+    fn test_crowding_labels_returned_parents() {
+        let mut rng = seeded_rng(3);
+        let weights = Weights { reproduce: 0, mutate: 0, crossover: 100, tree_height: 3, limits: TreeLimits::new(0, 0, LimitPolicy::ReturnParent) };
+        let noise = |_: &Tree, rng: &mut Rng| SimpleFitness::new(vec![("noise", rng.next_f32())]);
+
+        let mut pop: Population<Tree, SimpleFitness> = Population::new(10, 0);
+        for i in 0..10 {
+            pop.add(Tree::Node(Box::new(Tree::Leaf(i)), Box::new(Tree::Leaf(i))));
+        }
+        pop.score(&noise, &mut rng);
+
+        // Nothing fits the limits, so every survivor is a copy of a parent,
+        // even the ones that won from their parent by luck
+        let pop = deterministic_crowding(pop, &weights, &noise, structural_distance, &mut rng);
+        assert_eq!(10, pop.limit_stats.parents_returned);
+        assert!(pop.origins.iter().all(|o| o.operator == Operator::Reproduce && o.parents.len() == 1));
+        assert!(pop.ages.iter().all(|&a| a == 1));
+    }
+}
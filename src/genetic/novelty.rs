@@ -0,0 +1,212 @@
+use super::{Fitness, FitnessMut, ScoreCard, Scores};
+use super::super::{Number, Population};
+use super::super::num::sum;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+/// Fitness that describes how a program behaves, for novelty search.
+///
+/// The behavior is a vector of numbers that characterizes what a program does
+/// rather than how well it does it, such as the final position of a robot in
+/// a maze.
+pub trait Behavior: Fitness {
+    fn behavior(&self) -> &[Number];
+}
+
+/// Fitness that consists of a ScoreCard and a behavior.
+///
+/// Return this from the fitness function to use novelty search without a
+/// custom fitness type.
+#[derive(Clone,RustcEncodable)]
+pub struct NoveltyFitness {
+    score_card: ScoreCard,
+    behavior: Vec<Number>
+}
+
+impl NoveltyFitness {
+    pub fn new(scores: Scores, behavior: Vec<Number>) -> NoveltyFitness {
+        NoveltyFitness { score_card: ScoreCard::new(scores), behavior: behavior }
+    }
+}
+
+impl Fitness for NoveltyFitness {
+    fn score_card(&self) -> &ScoreCard { &self.score_card }
+}
+
+impl FitnessMut for NoveltyFitness {
+    fn score_card_mut(&mut self) -> &mut ScoreCard { &mut self.score_card }
+}
+
+impl Behavior for NoveltyFitness {
+    fn behavior(&self) -> &[Number] { &self.behavior }
+}
+
+/// Archive of novel behaviors seen during a run.
+///
+/// Novelty search (Lehman & Stanley, 2008) rewards programs for behaving
+/// differently from what has been seen before, instead of for their score.
+/// This helps on deceptive problems, where the score leads the search into a
+/// dead end. The novelty of a program is the average distance from its
+/// behavior to the `k` nearest behaviors in the population and the archive.
+///
+/// Call `apply()` after every scoring of the population, then select with
+/// `tournament_selection` or any other selector:
+///
+/// ```ignore
+/// let mut archive = NoveltyArchive::new(15, 2.0, 1000);
+/// for _ in 0..generations {
+///     pop.score(fitness, &mut rng);
+///     archive.apply(&mut pop, 1.0);
+///     pop = evolve(pop, &weights, &mut rng, selector);
+/// }
+/// ```
+pub struct NoveltyArchive {
+    /// Number of nearest neighbors to average the distance over
+    pub k: usize,
+
+    /// Minimum novelty for a behavior to be added to the archive
+    pub threshold: Number,
+
+    /// Maximum number of behaviors in the archive. When the archive is full,
+    /// the oldest behaviors are forgotten first.
+    pub capacity: usize,
+
+    behaviors: Vec<Vec<Number>>
+}
+
+impl NoveltyArchive {
+    pub fn new(k: usize, threshold: Number, capacity: usize) -> NoveltyArchive {
+        NoveltyArchive { k: k, threshold: threshold, capacity: capacity, behaviors: vec![] }
+    }
+
+    /// Reward the programs of a scored population for their novelty.
+    ///
+    /// The objective of every program becomes a blend of its novelty and its
+    /// score (see `ScoreCard::reward_novelty`), where a `weight` of 1 ignores
+    /// the score, and 0 ignores the novelty. Novelties are computed in
+    /// parallel. Afterwards, the behaviors with a novelty of at least the
+    /// threshold are added to the archive.
+    ///
+    /// Returns the number of behaviors that were added.
+    pub fn apply<P, F>(&mut self, pop: &mut Population<P, F>, weight: Number) -> usize
+        where P: Clone+Sync,
+              F: FitnessMut+Behavior+Sync
+    {
+        let mut novelties = Vec::with_capacity(pop.n());
+        {
+            let scores = &pop.scores;
+            let archive = &*self;
+            (0..scores.len()).collect::<Vec<usize>>().par_iter().weight_max()
+                .map(|&i| {
+                    let others = scores.iter().enumerate()
+                        .filter(|&(j, _)| j != i)
+                        .map(|(_, f)| f.behavior());
+                    archive.novelty(scores[i].behavior(), others)
+                })
+                .collect_into(&mut novelties);
+        }
+
+        let mut added = 0;
+        for (fitness, novelty) in pop.scores.iter_mut().zip(novelties) {
+            fitness.score_card_mut().reward_novelty(novelty, weight);
+            if novelty >= self.threshold && self.capacity > 0 {
+                self.behaviors.push(fitness.behavior().to_vec());
+                added += 1;
+            }
+        }
+        if self.behaviors.len() > self.capacity {
+            let excess = self.behaviors.len() - self.capacity;
+            self.behaviors.drain(..excess);
+        }
+        added
+    }
+
+    /// Average distance from a behavior to its `k` nearest neighbors among
+    /// the given behaviors and the archive.
+    pub fn novelty<'a, I: Iterator<Item=&'a [Number]>>(&'a self, behavior: &[Number], others: I) -> Number {
+        let mut distances = others
+            .chain(self.behaviors.iter().map(|b| &b[..]))
+            .map(|other| behavior_distance(behavior, other))
+            .collect::<Vec<_>>();
+        if distances.is_empty() {
+            return 0.0;
+        }
+
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let k = self.k.min(distances.len()).max(1);
+        sum(distances[..k].iter().cloned()) / k as Number
+    }
+
+    /// The behaviors in the archive, oldest first.
+    pub fn behaviors(&self) -> &[Vec<Number>] {
+        &self.behaviors
+    }
+
+    pub fn len(&self) -> usize {
+        self.behaviors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.behaviors.is_empty()
+    }
+}
+
+/// Euclidean distance between two behaviors, where a missing element counts as 0.
+fn behavior_distance(b1: &[Number], b2: &[Number]) -> Number {
+    let n = b1.len().max(b2.len());
+    sum((0..n).map(|i| (b1.get(i).cloned().unwrap_or(0.0) - b2.get(i).cloned().unwrap_or(0.0)).powi(2))).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn population(programs: Vec<(f32, Vec<Number>)>) -> Population<u32, NoveltyFitness> {
+        let mut pop = Population::new(programs.len(), 0);
+        for (i, (score, behavior)) in programs.into_iter().enumerate() {
+            pop.add(i as u32);
+            pop.scores.push(NoveltyFitness::new(vec![("score", score)], behavior));
+        }
+        pop
+    }
+
+    #[test]
+    fn test_novelty_wins() {
+        let mut pop = population(vec![(5.0, vec![0.0, 0.0]), (5.0, vec![0.0, 0.0]), (1.0, vec![3.0, 4.0])]);
+        let mut archive = NoveltyArchive::new(1, 4.0, 10);
+
+        assert_eq!(1, archive.apply(&mut pop, 1.0));
+
+        // The program that behaves differently wins, even with the worst score
+        assert_eq!(5.0, pop.scores[2].score_card().objective());
+        assert!(pop.scores[2].score_card() > pop.scores[0].score_card());
+        assert_eq!(1.0, pop.scores[2].score_card().total_score());
+        assert_eq!(&[vec![3.0, 4.0]], archive.behaviors());
+    }
+
+    #[test]
+    fn test_archived_behavior_is_not_novel() {
+        let mut archive = NoveltyArchive::new(1, 0.0, 2);
+        archive.apply(&mut population(vec![(1.0, vec![3.0, 4.0])]), 1.0);
+        assert_eq!(1, archive.len());
+
+        let mut pop = population(vec![(1.0, vec![3.0, 4.0])]);
+        archive.apply(&mut pop, 1.0);
+        assert_eq!(0.0, pop.scores[0].score_card().objective());
+        assert_eq!(5.0, archive.novelty(&[0.0, 0.0], vec![].into_iter()));
+
+        // The oldest behaviors are forgotten when the archive is full
+        archive.apply(&mut population(vec![(1.0, vec![0.0, 0.0])]), 1.0);
+        assert_eq!(&[vec![3.0, 4.0], vec![0.0, 0.0]], archive.behaviors());
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut pop = population(vec![(4.0, vec![0.0]), (2.0, vec![2.0])]);
+        let mut archive = NoveltyArchive::new(1, 100.0, 10);
+        archive.apply(&mut pop, 0.5);
+        assert_eq!(3.0, pop.scores[0].score_card().objective());
+        assert_eq!(2.0, pop.scores[1].score_card().objective());
+        assert!(archive.is_empty());
+    }
+}
@@ -1,18 +1,164 @@
+use std::cmp::Ordering;
 use rand::Rng;
 use super::fitness::Fitness;
-use super::super::AstNode;
+use super::super::{AstNode, Number};
+use super::super::ast::size;
 use super::super::Population;
+use super::super::num::sum;
 
 /// Return the winner from a tournament of size N, randomly picked from the scored population.
 pub fn tournament_selection<'a, P, F>(tournament_size: usize, pop: &'a Population<P, F>, rng: &mut Rng) -> &'a P
     where P: AstNode+Clone+Sync,
           F: Fitness+Send
+{
+    tournament(tournament_size, pop, rng, |i, j| pop.scores[i].score_card().cmp(pop.scores[j].score_card()))
+}
+
+/// Tournament selection with lexicographic parsimony pressure (Luke & Panait, 2002).
+///
+/// Works like `tournament_selection`, but if several candidates in the
+/// tournament have the same score, the one with the fewest nodes wins. This is
+/// especially effective for problems where many programs end up with the same
+/// score, like the Santa Fe ant trail.
+pub fn lexicographic_tournament_selection<'a, P, F>(tournament_size: usize, pop: &'a Population<P, F>, rng: &mut Rng) -> &'a P
+    where P: AstNode+Clone+Sync,
+          F: Fitness+Send
+{
+    tournament(tournament_size, pop, rng, |i, j| {
+        match pop.scores[i].score_card().cmp(pop.scores[j].score_card()) {
+            Ordering::Equal => size(&pop.population[j]).cmp(&size(&pop.population[i])),
+            ordering => ordering
+        }
+    })
+}
+
+/// Double tournament selection (Luke & Panait, 2002).
+///
+/// Holds two fitness tournaments of size `tournament_size`, then picks the
+/// smaller of the two winners with probability `parsimony / 2`. Sensible
+/// values for `parsimony` are between 1 (no parsimony pressure) and 2 (always
+/// pick the smaller program); 1.4 works well for most problems.
+pub fn double_tournament_selection<'a, P, F>(tournament_size: usize, parsimony: Number, pop: &'a Population<P, F>, rng: &mut Rng) -> &'a P
+    where P: AstNode+Clone+Sync,
+          F: Fitness+Send
+{
+    let one = tournament_selection(tournament_size, pop, rng);
+    let two = tournament_selection(tournament_size, pop, rng);
+
+    let (smaller, larger) = if size(one) <= size(two) { (one, two) } else { (two, one) };
+    if rng.next_f32() < parsimony / 2.0 { smaller } else { larger }
+}
+
+/// Tarpeian bloat control (Poli, 2003).
+///
+/// Every program that is larger than the average program in the population is
+/// marked as a victim with a given probability. Victims lose every tournament
+/// against programs that aren't victims, as if their fitness had been set to
+/// the worst possible value. Because this never changes the fitness structures
+/// themselves, it works for every type of `Fitness`.
+///
+/// Create a new instance every generation, after scoring the population:
+///
+/// ```ignore
+/// let tarpeian = Tarpeian::new(&pop, 0.3, &mut rng);
+/// pop = evolve(pop, &weights, &mut rng, |p, r| tarpeian.tournament_selection(TOURNAMENT_SIZE, p, r));
+/// ```
+pub struct Tarpeian {
+    victims: Vec<bool>
+}
+
+impl Tarpeian {
+    pub fn new<P, F>(pop: &Population<P, F>, probability: Number, rng: &mut Rng) -> Tarpeian
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Send
+    {
+        let sizes = pop.population.iter().map(|p| size(p)).collect::<Vec<usize>>();
+        let avg_size = sum(sizes.iter().map(|s| *s as Number)) / pop.n() as Number;
+        Tarpeian {
+            victims: sizes.into_iter().map(|s| s as Number > avg_size && rng.next_f32() < probability).collect()
+        }
+    }
+
+    /// Whether the program at the given index in the population is a victim.
+    pub fn is_victim(&self, i: usize) -> bool {
+        self.victims[i]
+    }
+
+    /// Return the winner from a tournament of size N, where victims always lose.
+    pub fn tournament_selection<'a, P, F>(&self, tournament_size: usize, pop: &'a Population<P, F>, rng: &mut Rng) -> &'a P
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Send
+    {
+        tournament(tournament_size, pop, rng, |i, j| {
+            match self.victims[j].cmp(&self.victims[i]) {
+                Ordering::Equal if !self.victims[i] => pop.scores[i].score_card().cmp(pop.scores[j].score_card()),
+                ordering => ordering
+            }
+        })
+    }
+}
+
+/// Hold a tournament between N random individuals, picking the greatest one according to `cmp`.
+fn tournament<'a, P, F, C>(tournament_size: usize, pop: &'a Population<P, F>, rng: &mut Rng, cmp: C) -> &'a P
+    where P: AstNode+Clone+Sync,
+          F: Fitness+Send,
+          C: Fn(usize, usize) -> Ordering
 {
     // Generate N random indexes. Slightly faster than rand::sample(), don't care about
     // the inaccuracy introduced by sampling with replacement.
     let count = pop.n();
     let candidate_indexes = (0..tournament_size).map(|_| rng.next_u64() as usize % count);
 
-    let (_, winner_i) = candidate_indexes.map(|i| (&pop.scores[i], i)).max_by_key(|f| f.0.score_card()).unwrap();
+    let winner_i = candidate_indexes.max_by(|i, j| cmp(*i, *j)).unwrap();
     &pop.population[winner_i]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::SimpleFitness;
+    use super::super::super::Population;
+    use super::super::super::seed::seeded_rng;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum List {
+        Cons(Box<List>),
+        Nil
+    }
+
+    impl_astnode!(List, 0,
+                  int Cons(next),
+                  leaf Nil());
+
+    fn population() -> Population<List, SimpleFitness> {
+        let mut pop = Population::new(2, 0);
+        pop.add(List::Cons(Box::new(List::Nil)));
+        pop.add(List::Nil);
+        pop.scores.push(SimpleFitness::new(vec![("score", 1.0)]));
+        pop.scores.push(SimpleFitness::new(vec![("score", 1.0)]));
+        pop
+    }
+
+    #[test]
+    fn test_lexicographic_prefers_smaller() {
+        let pop = population();
+        let mut rng = seeded_rng(1);
+        for _ in 0..10 {
+            assert_eq!(&List::Nil, lexicographic_tournament_selection(20, &pop, &mut rng));
+        }
+    }
+
+    #[test]
+    fn test_tarpeian_victims_lose() {
+        let mut pop = population();
+        pop.scores[0] = SimpleFitness::new(vec![("score", 2.0)]);
+        let mut rng = seeded_rng(1);
+
+        let tarpeian = Tarpeian::new(&pop, 1.0, &mut rng);
+        assert!(tarpeian.is_victim(0));
+        assert!(!tarpeian.is_victim(1));
+        for _ in 0..10 {
+            assert_eq!(&List::Nil, tarpeian.tournament_selection(20, &pop, &mut rng));
+        }
+    }
+}
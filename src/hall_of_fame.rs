@@ -0,0 +1,161 @@
+use super::{AstNode, Population, Fitness, ScoreCard};
+use super::ast::structural_hash;
+use std::collections::HashMap;
+
+/// A program in the hall of fame.
+#[derive(Clone,RustcEncodable)]
+pub struct Famous<P> {
+    pub program: P,
+    pub score_card: ScoreCard,
+
+    /// Generation in which the program was first found
+    pub generation: u32,
+
+    /// Structural hash of the program
+    pub hash: u64
+}
+
+/// The best distinct programs seen during a run.
+///
+/// Without elitism, the best program of a run can get lost in a later
+/// generation. Update the hall of fame with every scored generation to keep the
+/// `capacity` best programs of the whole run, best first. Programs with the
+/// same structure are only kept once, with the generation in which they were
+/// first found, even if they were pushed out of the hall of fame in between.
+///
+/// The hall of fame can be serialized using `rustc_serialize`.
+#[derive(Clone,RustcEncodable)]
+pub struct HallOfFame<P> {
+    pub capacity: usize,
+    entries: Vec<Famous<P>>,
+
+    /// Generation in which every program that was ever admitted was first found, by structural hash
+    first_found: HashMap<u64, u32>
+}
+
+impl <P: AstNode+Clone+Sync> HallOfFame<P> {
+    pub fn new(capacity: usize) -> HallOfFame<P> {
+        HallOfFame { capacity: capacity, entries: Vec::with_capacity(capacity + 1), first_found: HashMap::new() }
+    }
+
+    /// Consider all programs of a scored population for the hall of fame.
+    ///
+    /// Returns the number of programs that were added.
+    pub fn update<F: Fitness+Send>(&mut self, pop: &Population<P, F>) -> usize {
+        let mut added = 0;
+        for (program, fitness) in pop.population.iter().zip(pop.scores.iter()) {
+            if self.offer(program, fitness.score_card(), pop.generation) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Consider a single program for the hall of fame.
+    ///
+    /// Returns whether the program was added.
+    pub fn offer(&mut self, program: &P, score_card: &ScoreCard, generation: u32) -> bool {
+        if self.capacity == 0 { return false; }
+        if self.entries.len() == self.capacity && *score_card <= self.entries[self.capacity - 1].score_card {
+            return false;
+        }
+
+        let hash = structural_hash(program);
+        if self.entries.iter().any(|e| e.hash == hash) {
+            return false;
+        }
+
+        // Insert after all entries that are at least as good, so that older programs win ties
+        let i = self.entries.iter().position(|e| e.score_card < *score_card).unwrap_or(self.entries.len());
+        let generation = *self.first_found.entry(hash).or_insert(generation);
+        self.entries.insert(i, Famous {
+            program: program.clone(),
+            score_card: score_card.clone(),
+            generation: generation,
+            hash: hash
+        });
+        self.entries.truncate(self.capacity);
+        true
+    }
+
+    /// The programs in the hall of fame, best first.
+    pub fn entries(&self) -> &[Famous<P>] {
+        &self.entries
+    }
+
+    /// The best program seen so far.
+    pub fn best(&self) -> Option<&Famous<P>> {
+        self.entries.first()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::genetic::SimpleFitness;
+    use rustc_serialize::json;
+
+    #[derive(Clone,PartialEq,Debug,RustcEncodable)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn population(generation: u32, programs: Vec<(u32, f32)>) -> Population<Tree, SimpleFitness> {
+        let mut pop = Population::new(programs.len(), generation);
+        for (leaf, score) in programs {
+            pop.add(Tree::Leaf(leaf));
+            pop.scores.push(SimpleFitness::new(vec![("score", score)]));
+        }
+        pop
+    }
+
+    #[test]
+    fn test_keeps_best_distinct() {
+        let mut hof = HallOfFame::new(2);
+        assert_eq!(2, hof.update(&population(0, vec![(1, 1.0), (2, 5.0), (1, 1.0)])));
+        assert_eq!(1, hof.update(&population(1, vec![(3, 3.0), (2, 5.0), (4, 0.0)])));
+
+        let entries = hof.entries();
+        assert_eq!(2, entries.len());
+        assert_eq!(Tree::Leaf(2), entries[0].program);
+        assert_eq!(0, entries[0].generation);
+        assert_eq!(Tree::Leaf(3), entries[1].program);
+        assert_eq!(1, entries[1].generation);
+    }
+
+    #[test]
+    fn test_returning_program_keeps_generation() {
+        let mut hof = HallOfFame::new(1);
+        hof.update(&population(0, vec![(1, 1.0)]));
+        hof.update(&population(1, vec![(2, 2.0)]));
+        assert_eq!(Tree::Leaf(2), hof.entries()[0].program);
+
+        // Pushed out and found again later
+        hof.update(&population(5, vec![(1, 3.0)]));
+        assert_eq!(Tree::Leaf(1), hof.entries()[0].program);
+        assert_eq!(0, hof.entries()[0].generation);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut hof = HallOfFame::new(3);
+        hof.update(&population(4, vec![(1, 1.0)]));
+
+        let encoded = json::encode(&hof).unwrap();
+        let decoded = json::Json::from_str(&encoded).unwrap();
+        assert_eq!(Some(4), decoded.find_path(&["entries"]).and_then(|e| e.as_array()).and_then(|e| e[0].find("generation")).and_then(|g| g.as_u64()));
+    }
+}
@@ -17,7 +17,10 @@
 /// }
 ///
 /// // Notice sub-trees are indicated by a name, but data fields are
-/// // indicated by (data name).
+/// // indicated by (data name). Data fields must implement `Debug`, so they
+/// // can be included in the label of the node. Without the data fields in
+/// // the label, programs that only differ in their data would count as the
+/// // same program for caching, the hall of fame and tree distances.
 ///
 /// impl_astnode!(Tree, 666,
 ///               leaf Leaf((data value |rng: &mut ::rand::Rng| (rng.next_u32() % 100) as i32)),
@@ -60,6 +63,23 @@ macro_rules! impl_astnode {
     (@repcrea $old_child:ident $new_child:ident $enum_name:ident $case_name:ident ()) => { $enum_name::$case_name };
     (@repcrea $old_child:ident $new_child:ident $enum_name:ident $case_name:ident ($($fields:tt),+)) => { $enum_name::$case_name($( impl_astnode!(@repret $old_child $new_child $fields) ),+) };
 
+    // Field matchers for labels, which only bind data fields
+    (@labcap (data $i:ident $($gen:expr)*)) => { ref $i };
+    (@labcap $i:ident) => { _ };
+
+    // Matching pattern for labels, for enum variants with and without parameters
+    (@labpat $enum_name:ident $case_name:ident ()) => { $enum_name::$case_name };
+    (@labpat $enum_name:ident $case_name:ident ($($fields:tt),+)) => { $enum_name::$case_name($( impl_astnode!(@labcap $fields) ),+) };
+
+    // Make vector of the formatted data fields
+    (@labvec () ($($acc:ident)*)) => { vec![$(format!("{:?}", $acc)),*] };
+    (@labvec ((data $field:ident $($gen:expr)*) $($fields:tt)*) ($($acc:ident)*)) => {
+        impl_astnode!(@labvec ($($fields)*) ($($acc)* $field))
+    };
+    (@labvec ($field:ident $($fields:tt)*) ($($acc:ident)*)) => {
+        impl_astnode!(@labvec ($($fields)*) ($($acc)*))
+    };
+
     // Constructor call, for random variants with and without parameters
     (@randcrea $weights:ident $rng:ident $enum_name:ident $case_name:ident ()) => { $enum_name::$case_name };
     (@randcrea $weights:ident $rng:ident $enum_name:ident $case_name:ident ($($fields:tt),+)) => {
@@ -98,6 +118,16 @@ macro_rules! impl_astnode {
                     ),*
                 })
             }
+
+            fn label(&self) -> String {
+                match *self {
+                    $(
+                        impl_astnode!(@labpat $enum_name $case_name($($fields),*))
+                            =>
+                        $crate::node_label(stringify!($case_name), impl_astnode!(@labvec ($($fields)*) ()))
+                    ),*
+                }
+            }
         }
 
         impl $crate::RandNode for $enum_name {
@@ -133,6 +163,12 @@ mod tests {
         assert_eq!(2, node.children().len());
     }
 
+    #[test]
+    fn test_label() {
+        assert_eq!("Leaf(1)", Tree::Leaf(1).label());
+        assert_eq!("Node", Tree::Node(Box::new(Tree::Leaf(1)), Box::new(Tree::Leaf(2))).label());
+    }
+
     #[test]
     fn copy_data() {
         let node = Tree::Leaf(1);
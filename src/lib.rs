@@ -8,8 +8,11 @@
 //!   (which can usually be autogenerated using `impl_astnode!()`).
 //! - A fitness function evaluating the success of a given program.
 //!
+//! Data fields of nodes generated by `impl_astnode!()` must implement `Debug`.
+//!
 //! Create `random_population` from your types, then call `evolve` repeatedly to
-//! improve the fitness of the population.
+//! improve the fitness of the population. Use a generator created by
+//! `seeded_rng` to make the entire run reproducible.
 //!
 //! For a working example, see the `santa_fe_ant` in the `examples/` directory.
 
@@ -22,17 +25,44 @@ extern crate rayon;
 #[macro_use] pub mod impl_astnode;
 
 mod ast;
-pub use ast::{AstNode, Mutatable, clone_or_replace, depth};
+pub use ast::{AstNode, Mutatable, clone_or_replace, depth, size, position, structural_hash, node_label};
 
 mod population;
-pub use self::population::Population;
+pub use self::population::{Population, CreatureScore};
+
+mod genealogy;
+pub use self::genealogy::{Genealogy, Ancestor, Origin, Operator};
+
+mod evaluation;
+pub use self::evaluation::{Budget, Resampling, FailurePolicy, Failure, EvaluationError};
+
+mod cache;
+pub use self::cache::FitnessCache;
 
 mod random_pop;
 pub use self::random_pop::{random_population, RandNode, NodeWeights, retain_best};
 
 pub mod num;
 
+mod distance;
+pub use self::distance::{tree_edit_distance, tree_diff, TreeDiff, Edit};
+
+mod diversity;
+pub use self::diversity::{Diversity, DISTANCE_SAMPLE_PAIRS};
+
+mod stats;
+pub use self::stats::{GenerationStats, Distribution};
+
+mod hall_of_fame;
+pub use self::hall_of_fame::{HallOfFame, Famous};
+
+mod run_log;
+pub use self::run_log::{RunLogger, JsonLinesLogger, CsvLogger, Parameters};
+
+mod seed;
+pub use self::seed::{seeded_rng, derive_rng};
+
 pub mod genetic;
-pub use genetic::{ScoreCard, Fitness};
+pub use genetic::{ScoreCard, Fitness, FitnessMut, FromScoreCard};
 
 pub use num::Number;
@@ -1,4 +1,5 @@
 //! Numeric helper functions
+use std::cmp::Ordering;
 
 /// The general number type used by all simulations
 pub type Number = f32;
@@ -36,6 +37,37 @@ pub fn sum<I: Iterator<Item=Number>>(iter: I) -> Number {
     total
 }
 
+/// Arithmetic mean of a list of numbers, or None if the list is empty.
+pub fn mean(xs: &[Number]) -> Option<Number> {
+    if xs.is_empty() { return None; }
+    Some(sum(xs.iter().cloned()) / xs.len() as Number)
+}
+
+/// Median of a list of numbers, or None if the list is empty.
+///
+/// For an even number of values, this is the mean of the middle two.
+pub fn median(xs: &[Number]) -> Option<Number> {
+    if xs.is_empty() { return None; }
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        Some((sorted[mid - 1] + sorted[mid]) / 2.0)
+    } else {
+        Some(sorted[mid])
+    }
+}
+
+/// Population variance of a list of numbers, or None if the list is empty.
+pub fn variance(xs: &[Number]) -> Option<Number> {
+    mean(xs).map(|m| sum(xs.iter().map(|&x| square(x - m))) / xs.len() as Number)
+}
+
+/// Population standard deviation of a list of numbers, or None if the list is empty.
+pub fn std_dev(xs: &[Number]) -> Option<Number> {
+    variance(xs).map(|v| v.sqrt())
+}
+
 /// Crop a value to an N-sized torus
 pub fn torus(x: i32, n: i32) -> i32 {
     let k = if x < 0 { -x / n + 1 } else { 0 };
@@ -56,4 +88,15 @@ mod test {
         assert_eq!(1, torus(33, 32));
         assert_eq!(1, torus(-31, 32));
     }
+
+    #[test]
+    fn test_statistics() {
+        let xs = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(Some(5.0), mean(&xs));
+        assert_eq!(Some(4.5), median(&xs));
+        assert_eq!(Some(4.0), variance(&xs));
+        assert_eq!(Some(2.0), std_dev(&xs));
+        assert_eq!(Some(3.0), median(&[3.0, 1.0, 8.0]));
+        assert_eq!(None, std_dev(&[]));
+    }
 }
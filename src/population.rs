@@ -1,7 +1,14 @@
-use super::genetic::Fitness;
+use super::genetic::{Fitness, FitnessMut, FromScoreCard, Aggregation, score_ranges, ScoreCard, LimitStats, Equalizer, Direction};
+use super::evaluation::{evaluate, Budget, Resampling, FailurePolicy, EvaluationError};
 use rand::Rng;
-use super::Number;
-use super::num::{sum, partial_max};
+use super::{AstNode, Number, FitnessCache, GenerationStats, Origin};
+use super::ast::structural_hash;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use super::num::sum;
+use super::seed::derive_rng;
 use rayon::prelude::*;
 use rustc_serialize::Encodable;
 
@@ -18,7 +25,38 @@ pub struct Population<P: Clone+Sync, F: Fitness+Sized+Send> {
     pub generation: u32,
 
     /// Collection of fitness scores
-    pub scores: Vec<F>
+    pub scores: Vec<F>,
+
+    /// How often the tree limits were hit while producing this population
+    pub limit_stats: LimitStats,
+
+    /// Operator equalization to control the sizes of offspring, if any
+    pub equalizer: Option<Equalizer>,
+
+    /// Number of evaluations that failed while scoring this population
+    pub failures: usize,
+
+    /// For every program, whether it ran out of its evaluation budget
+    pub exhausted: Vec<bool>,
+
+    /// Time spent on scoring this population
+    pub scoring_time: Duration,
+
+    /// Time spent on producing this population from the previous one
+    pub variation_time: Duration,
+
+    /// Unique ID of every program
+    pub ids: Vec<u64>,
+
+    /// For every program, the parents and operator that produced it
+    pub origins: Vec<Origin>,
+
+    /// For every program, the number of generations its oldest genetic
+    /// material has been evolving. Random programs start at 0.
+    pub ages: Vec<u32>,
+
+    /// ID that will be given to the next program that is added
+    pub next_id: u64
 }
 
 impl <P: Clone+Sync, F: Fitness+Sized+Send> Population<P, F> {
@@ -29,13 +67,59 @@ impl <P: Clone+Sync, F: Fitness+Sized+Send> Population<P, F> {
         Population {
             population: Vec::with_capacity(n),
             scores: Vec::with_capacity(n),
-            generation: generation
+            generation: generation,
+            limit_stats: LimitStats::default(),
+            equalizer: None,
+            failures: 0,
+            exhausted: vec![],
+            scoring_time: Duration::new(0, 0),
+            variation_time: Duration::new(0, 0),
+            ids: Vec::with_capacity(n),
+            origins: Vec::with_capacity(n),
+            ages: Vec::with_capacity(n),
+            next_id: 0
         }
     }
 
     /// Add a single program to the population.
     pub fn add(&mut self, program: P) {
+        self.add_with_origin(program, Origin::random(), 0);
+    }
+
+    /// Add a single program to the population, recording where it came from
+    /// and its age.
+    ///
+    /// Returns the ID of the program.
+    pub fn add_with_origin(&mut self, program: P, origin: Origin, age: u32) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
         self.population.push(program);
+        self.ids.push(id);
+        self.origins.push(origin);
+        self.ages.push(age);
+        id
+    }
+
+    /// Return the index of a program, if it is part of this population.
+    ///
+    /// The program must be a reference into this population, such as a
+    /// program returned by a selection function, not an equal copy. The index
+    /// is worked out from the address of the program, so this takes constant
+    /// time.
+    pub fn index_of(&self, program: &P) -> Option<usize> {
+        let width = mem::size_of::<P>();
+        if width == 0 { return None; }
+        let start = self.population.as_ptr() as usize;
+        let offset = (program as *const P as usize).wrapping_sub(start);
+        let i = offset / width;
+        if offset % width == 0 && i < self.n() { Some(i) } else { None }
+    }
+
+    /// Return the ID of a program, if it is part of this population.
+    ///
+    /// See `index_of()`.
+    pub fn id_of(&self, program: &P) -> Option<u64> {
+        self.index_of(program).and_then(|i| self.ids.get(i).cloned())
     }
 
     pub fn n(&self) -> usize {
@@ -43,19 +127,276 @@ impl <P: Clone+Sync, F: Fitness+Sized+Send> Population<P, F> {
     }
 
     /// Apply a scoring function to the entire population.
-    pub fn score<S>(&mut self, scoring_fn: S, _: &mut Rng)
+    ///
+    /// Programs are scored in parallel. Every program gets its own random
+    /// number generator, derived from a seed drawn from `rng`, the generation
+    /// and the program's index, so scoring is reproducible.
+    pub fn score<S>(&mut self, scoring_fn: S, rng: &mut Rng)
         where S: Fn(&P, &mut Rng) -> F + Sync
     {
-        self.population.par_iter().weight_max().map(|p| scoring_fn(p, &mut ::rand::thread_rng())).collect_into(&mut self.scores);
+        let start = Instant::now();
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        self.population.par_iter().enumerate().weight_max()
+            .map(|(i, p)| scoring_fn(p, &mut derive_rng(seed, generation, i)))
+            .collect_into(&mut self.scores);
+        self.scoring_time = start.elapsed();
+    }
+
+    /// Apply a fallible scoring function to the entire population.
+    ///
+    /// Like `score`, but the scoring function may return an error. Panics in
+    /// the scoring function are caught and treated as errors as well. Failed
+    /// evaluations are handled according to the policy: programs that can't
+    /// be evaluated get the worst possible score (see `ScoreCard::worst`), in
+    /// the direction of the programs that could be evaluated, unless the
+    /// policy is to abort, in which case the first failure is returned. After a failure,
+    /// the programs that haven't started evaluating yet are skipped.
+    ///
+    /// Returns the number of evaluations that failed, which is also recorded
+    /// in `failures`.
+    pub fn try_score<S, E>(&mut self, scoring_fn: S, policy: FailurePolicy, rng: &mut Rng) -> Result<usize, EvaluationError<E>>
+        where S: Fn(&P, &mut Rng) -> Result<F, E> + Sync,
+              F: FromScoreCard,
+              E: Send
+    {
+        let start = Instant::now();
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        let aborted = AtomicBool::new(false);
+        let mut results = Vec::with_capacity(self.n());
+        self.population.par_iter().enumerate().weight_max()
+            .map(|(i, p)| {
+                if aborted.load(Ordering::Relaxed) { return None; }
+                let (result, failures) = evaluate(&scoring_fn, p, policy, &mut derive_rng(seed, generation, i));
+                if result.is_err() && policy == FailurePolicy::Abort {
+                    aborted.store(true, Ordering::Relaxed);
+                }
+                Some((result, failures))
+            })
+            .collect_into(&mut results);
+
+        let direction = results.iter()
+            .filter_map(|r| match *r { Some((Ok(ref fitness), _)) => Some(fitness.score_card().direction()), _ => None })
+            .next()
+            .unwrap_or(Direction::Maximize);
+
+        self.scores.clear();
+        self.failures = 0;
+        for (i, (result, failures)) in results.into_iter().enumerate().filter_map(|(i, r)| r.map(|r| (i, r))) {
+            self.failures += failures;
+            match result {
+                Ok(fitness) => self.scores.push(fitness),
+                Err(failure) => {
+                    if policy == FailurePolicy::Abort {
+                        self.scores.clear();
+                        self.scoring_time = start.elapsed();
+                        return Err(EvaluationError { index: i, failure: failure });
+                    }
+                    self.scores.push(F::from_score_card(ScoreCard::worst(direction)));
+                }
+            }
+        }
+        self.scoring_time = start.elapsed();
+        Ok(self.failures)
+    }
+
+    /// Apply a scoring function with an evaluation budget to the entire population.
+    ///
+    /// Like `score`, but every program gets a fresh copy of `budget`, which the
+    /// scoring function should spend while evaluating the program. Records in
+    /// `exhausted` which programs ran out of budget, so that they can be
+    /// penalized uniformly using `penalize_exhausted`.
+    pub fn score_with_budget<S>(&mut self, scoring_fn: S, budget: &Budget, rng: &mut Rng)
+        where S: Fn(&P, &mut Rng, &mut Budget) -> F + Sync
+    {
+        let start = Instant::now();
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        let mut results = Vec::with_capacity(self.n());
+        self.population.par_iter().enumerate().weight_max()
+            .map(|(i, p)| {
+                let mut budget = budget.start();
+                let fitness = scoring_fn(p, &mut derive_rng(seed, generation, i), &mut budget);
+                (fitness, budget.is_exhausted())
+            })
+            .collect_into(&mut results);
+
+        let (scores, exhausted) = results.into_iter().unzip();
+        self.scores = scores;
+        self.exhausted = exhausted;
+        self.scoring_time = start.elapsed();
+    }
+
+    /// Add a penalty to the score of every program that ran out of its evaluation budget.
+    ///
+    /// The penalty is added to the ScoreCard under the label
+    /// "budget_exhausted", so it should be negative. The penalty counts against
+    /// the program regardless of the direction of its other scores. The rest
+    /// of the fitness is kept as-is, and a penalty from an earlier call is
+    /// replaced, so it is never counted twice.
+    pub fn penalize_exhausted(&mut self, penalty: Number)
+        where F: FitnessMut
+    {
+        for (fitness, &exhausted) in self.scores.iter_mut().zip(self.exhausted.iter()) {
+            if exhausted {
+                let score_card = fitness.score_card_mut();
+                *score_card = mem::replace(score_card, ScoreCard::new(vec![]))
+                    .with_score("budget_exhausted", penalty)
+                    .with_direction("budget_exhausted", Direction::Maximize);
+            }
+        }
+    }
+
+    /// Apply a noisy scoring function to the entire population.
+    ///
+    /// Every program is evaluated `resampling.samples` times, each time with a
+    /// different random number generator, and gets the average of the
+    /// resulting ScoreCards. Programs that were also in the previous
+    /// population scored with the same `resampling` are survivors, which are
+    /// either evaluated again or keep their earlier average.
+    ///
+    /// Every program keeps the fitness of its last evaluation, with the
+    /// ScoreCard replaced by the average ScoreCard, so any other state in the
+    /// fitness comes from that single evaluation.
+    pub fn score_resampled<S>(&mut self, scoring_fn: S, resampling: &mut Resampling<F>, rng: &mut Rng)
+        where S: Fn(&P, &mut Rng) -> F + Sync,
+              P: AstNode,
+              F: FitnessMut+Clone
+    {
+        let start = Instant::now();
+        let hashes = self.population.iter().map(|p| structural_hash(p)).collect::<Vec<u64>>();
+        let evaluate = hashes.iter()
+            .map(|h| resampling.reevaluate_survivors || resampling.previous(*h).is_none())
+            .collect::<Vec<bool>>();
+
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        let samples = resampling.samples;
+        let mut fresh = Vec::with_capacity(self.n());
+        self.population.par_iter().enumerate().weight_max()
+            .map(|(i, p)| {
+                if !evaluate[i] { return None; }
+                let mut total = ScoreCard::new(vec![]);
+                let mut last = None;
+                for k in 0..samples {
+                    let fitness = scoring_fn(p, &mut derive_rng(seed, generation, i * samples + k));
+                    total += fitness.score_card();
+                    last = Some(fitness);
+                }
+                last.map(|fitness| (total, fitness))
+            })
+            .collect_into(&mut fresh);
+
+        let mut history = HashMap::new();
+        self.scores.clear();
+        for (fresh, hash) in fresh.into_iter().zip(hashes) {
+            let previous = resampling.previous(hash).cloned();
+            let (total, count, mut fitness) = match (fresh, previous) {
+                (Some((total, fitness)), Some((previous, count, _))) => (previous + total, count + samples, fitness),
+                (Some((total, fitness)), None) => (total, samples, fitness),
+                (None, Some(previous)) => previous,
+                (None, None) => unreachable!()
+            };
+            *fitness.score_card_mut() = total.clone() / count as Number;
+            self.scores.push(fitness.clone());
+            history.insert(hash, (total, count, fitness));
+        }
+        resampling.remember(history);
+        self.scoring_time = start.elapsed();
+    }
+
+    /// Apply a scoring function to the entire population, reusing earlier results.
+    ///
+    /// Like `score`, but programs that are structurally identical to a program
+    /// in the cache, or to another program in this population, are not scored
+    /// again. Only use this with a deterministic scoring function, otherwise
+    /// programs keep the result of a single lucky (or unlucky) evaluation.
+    pub fn score_cached<S>(&mut self, scoring_fn: S, cache: &mut FitnessCache<F>, rng: &mut Rng)
+        where S: Fn(&P, &mut Rng) -> F + Sync,
+              P: AstNode,
+              F: Clone
+    {
+        let start = Instant::now();
+        let hashes = self.population.iter().map(|p| structural_hash(p)).collect::<Vec<u64>>();
+
+        // Decide for every program where its fitness is going to come from
+        let mut first_seen = HashMap::new();
+        let mut sources = Vec::with_capacity(self.n());
+        for (i, &hash) in hashes.iter().enumerate() {
+            if let Some(&j) = first_seen.get(&hash) {
+                cache.hits += 1;
+                sources.push(Source::SameAs(j));
+            } else {
+                first_seen.insert(hash, i);
+                sources.push(match cache.get(hash) {
+                    Some(fitness) => Source::Cached(fitness),
+                    None => Source::Evaluate
+                });
+            }
+        }
+
+        let evaluate = sources.iter().map(|s| match *s { Source::Evaluate => true, _ => false }).collect::<Vec<bool>>();
+        let seed = rng.next_u64();
+        let generation = self.generation;
+        let mut fresh = Vec::with_capacity(self.n());
+        self.population.par_iter().enumerate().weight_max()
+            .map(|(i, p)| if evaluate[i] { Some(scoring_fn(p, &mut derive_rng(seed, generation, i))) } else { None })
+            .collect_into(&mut fresh);
+
+        let mut scores: Vec<F> = Vec::with_capacity(self.n());
+        for ((source, fitness), hash) in sources.into_iter().zip(fresh).zip(hashes) {
+            let fitness = match source {
+                Source::Cached(fitness) => fitness,
+                Source::SameAs(j) => scores[j].clone(),
+                Source::Evaluate => {
+                    let fitness = fitness.unwrap();
+                    cache.insert(hash, fitness.clone());
+                    fitness
+                }
+            };
+            scores.push(fitness);
+        }
+        self.scores = scores;
+        self.scoring_time = start.elapsed();
+    }
+
+    /// Re-weight the subscores of every program before selection.
+    ///
+    /// Programs are compared by the aggregated objective afterwards. Call this
+    /// after scoring the population, since scoring resets the objectives.
+    pub fn aggregate(&mut self, aggregation: &Aggregation)
+        where F: FitnessMut
+    {
+        let ranges = if aggregation.normalize {
+            score_ranges(self.scores.iter().map(|f| f.score_card()))
+        } else {
+            vec![]
+        };
+        for fitness in self.scores.iter_mut() {
+            fitness.score_card_mut().aggregate(aggregation, &ranges);
+        }
     }
 
+    /// Return statistics of the scores and programs of this population.
+    ///
+    /// The diversity of the programs is estimated from `sample_pairs` pairs.
+    pub fn stats(&self, sample_pairs: usize) -> GenerationStats
+        where P: AstNode
+    {
+        GenerationStats::of(self, sample_pairs)
+    }
+
+    /// Return the average total score, leaving out programs that couldn't be evaluated.
     pub fn avg_score(&self) -> Number {
-        let total_score = sum(self.scores.iter().map(|f| f.score_card().total_score()));
-        total_score / (self.n() as Number)
+        let evaluated = self.scores.iter().map(|f| f.score_card()).filter(|c| !c.is_failed()).collect::<Vec<_>>();
+        let total_score = sum(evaluated.iter().map(|c| c.total_score()));
+        total_score / (evaluated.len() as Number)
     }
 
+    /// Return the total score of the best program, taking the direction of the scores into account.
     pub fn best_score(&self) -> Number {
-        partial_max(self.scores.iter().map(|f| f.score_card().total_score())).unwrap()
+        self.scores.iter().map(|f| f.score_card()).max().unwrap().total_score()
     }
 
     /// Return the best program from the population.
@@ -74,12 +415,24 @@ impl <P: Clone+Sync, F: Fitness+Sized+Send> Population<P, F> {
     /// Return the best N programs from the population.
     pub fn best_n<'a>(&self, n: usize) -> Vec<P>
     {
+        self.best_indexes(n).into_iter().map(|i| self.population[i].clone()).collect()
+    }
+
+    /// Return the indexes of the best N programs in the population, worst first.
+    pub fn best_indexes(&self, n: usize) -> Vec<usize> {
         let mut indexes : Vec<usize> = (0..self.n()).collect();
         indexes.sort_by_key(|i| self.scores[*i].score_card());
-        indexes[indexes.len() - n..].into_iter().map(|i| self.population[*i].clone()).collect()
+        indexes.split_off(self.n() - n)
     }
 }
 
+/// Where the fitness of a program comes from during cached scoring.
+enum Source<F> {
+    Cached(F),
+    SameAs(usize),
+    Evaluate
+}
+
 #[derive(RustcEncodable)]
 pub struct CreatureScore<'a, P: 'a, F: 'a>
     where P: Encodable, F: Encodable
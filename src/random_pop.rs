@@ -1,5 +1,5 @@
 use std::cmp::max;
-use super::{AstNode, Population, Fitness, Mutatable, Number};
+use super::{AstNode, Population, Fitness, Mutatable, Number, Origin, Operator};
 use rand::Rng;
 use rustc_serialize::Encodable;
 
@@ -46,9 +46,12 @@ pub fn retain_best<P, F, R>(frac: Number, pop: Population<P, F>, max_depth: usiz
     let filler = pop.n() - n;
     let mut ret = Population::new(n, 0);
     ret.generation = pop.generation + 1;
+    ret.next_id = pop.next_id;
 
-    for c in pop.best_n(n) {
-        ret.add(c);
+    // The survivors are reproduced, so they age like in evolve
+    for i in pop.best_indexes(n) {
+        let origin = Origin::new(Operator::Reproduce, vec![pop.ids[i]], None);
+        ret.add_with_origin(pop.population[i].clone(), origin, pop.ages[i] + 1);
     }
 
     for i in 0..filler {
@@ -109,9 +112,10 @@ const MIN_WEIGHT : i32 = 1;
 mod tests {
     use super::*;
     use super::super::{num, Number, depth};
-    use super::super::genetic::mutate_tree;
+    use super::super::seed::seeded_rng;
+    use super::super::genetic::{mutate_tree, SimpleFitness};
 
-    #[derive(Clone)]
+    #[derive(Clone,RustcEncodable)]
     enum List {
         Cons(Box<List>),
         Nil
@@ -153,4 +157,24 @@ mod tests {
             assert!((depth as Number) < target_height as Number * 1.5);
         }
     }
+
+    #[test]
+    fn test_retain_best_records_provenance() {
+        let mut pop: Population<List, SimpleFitness> = Population::new(4, 3);
+        for i in 0..4 {
+            pop.add(List::Nil);
+            pop.ages[i] = i as u32;
+            pop.scores.push(SimpleFitness::new(vec![("score", i as Number)]));
+        }
+        let ids = pop.ids.clone();
+
+        let ret = retain_best(0.5, pop, 2, &mut seeded_rng(1));
+
+        assert_eq!(4, ret.n());
+        assert_eq!(4, ret.generation);
+        assert_eq!(Origin::new(Operator::Reproduce, vec![ids[2]], None), ret.origins[0]);
+        assert_eq!(Origin::new(Operator::Reproduce, vec![ids[3]], None), ret.origins[1]);
+        assert_eq!(vec![3, 4, 0, 0], ret.ages);
+        assert_eq!(Origin::random(), ret.origins[2]);
+    }
 }
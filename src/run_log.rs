@@ -0,0 +1,243 @@
+use super::{AstNode, Population, Fitness, GenerationStats, Distribution};
+use super::diversity::DISTANCE_SAMPLE_PAIRS;
+use super::ast::size;
+use super::genetic::Label;
+use rustc_serialize::Encodable;
+use rustc_serialize::json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Write, BufWriter};
+use std::path::Path;
+
+/// Parameters of a run, which are repeated in every record.
+pub type Parameters = BTreeMap<String, String>;
+
+/// Something that records every generation of a run.
+///
+/// Call `log()` once per generation, after the population has been scored.
+pub trait RunLogger<P: Clone+Sync, F: Fitness+Send> {
+    fn log(&mut self, pop: &Population<P, F>) -> io::Result<()>;
+}
+
+/// Logs one JSON object per generation, on its own line.
+///
+/// Every record contains the parameters of the run, the `GenerationStats` and
+/// the champion of the generation.
+pub struct JsonLinesLogger<W: Write> {
+    writer: W,
+    parameters: Parameters,
+
+    /// Number of pairs of programs to estimate the diversity from
+    pub sample_pairs: usize
+}
+
+impl <W: Write> JsonLinesLogger<W> {
+    pub fn new(writer: W, parameters: Parameters) -> JsonLinesLogger<W> {
+        JsonLinesLogger { writer: writer, parameters: parameters, sample_pairs: DISTANCE_SAMPLE_PAIRS }
+    }
+}
+
+impl JsonLinesLogger<BufWriter<File>> {
+    /// Create a logger that writes to a new file.
+    pub fn create<T: AsRef<Path>>(path: T, parameters: Parameters) -> io::Result<JsonLinesLogger<BufWriter<File>>> {
+        Ok(JsonLinesLogger::new(BufWriter::new(File::create(path)?), parameters))
+    }
+}
+
+#[derive(RustcEncodable)]
+struct Record<'a, C: 'a> {
+    parameters: &'a Parameters,
+    stats: GenerationStats,
+    champion: C
+}
+
+impl <W, P, F> RunLogger<P, F> for JsonLinesLogger<W>
+    where W: Write,
+          P: AstNode+Clone+Sync+Encodable,
+          F: Fitness+Send+Encodable
+{
+    fn log(&mut self, pop: &Population<P, F>) -> io::Result<()> {
+        let record = Record {
+            parameters: &self.parameters,
+            stats: pop.stats(self.sample_pairs),
+            champion: pop.champion()
+        };
+        let line = json::encode(&record).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()
+    }
+}
+
+/// Logs one row of comma-separated values per generation.
+///
+/// The columns are the parameters of the run, the `GenerationStats` including
+/// diversity, and the total score and size of the champion. Subscores get columns for the labels
+/// that are in the first generation that is logged.
+pub struct CsvLogger<W: Write> {
+    writer: W,
+    parameters: Parameters,
+    labels: Option<Vec<Label>>,
+
+    /// Number of pairs of programs to estimate the diversity from
+    pub sample_pairs: usize
+}
+
+impl <W: Write> CsvLogger<W> {
+    pub fn new(writer: W, parameters: Parameters) -> CsvLogger<W> {
+        CsvLogger { writer: writer, parameters: parameters, labels: None, sample_pairs: DISTANCE_SAMPLE_PAIRS }
+    }
+}
+
+impl CsvLogger<BufWriter<File>> {
+    /// Create a logger that writes to a new file.
+    pub fn create<T: AsRef<Path>>(path: T, parameters: Parameters) -> io::Result<CsvLogger<BufWriter<File>>> {
+        Ok(CsvLogger::new(BufWriter::new(File::create(path)?), parameters))
+    }
+}
+
+impl <W, P, F> RunLogger<P, F> for CsvLogger<W>
+    where W: Write,
+          P: AstNode+Clone+Sync,
+          F: Fitness+Send
+{
+    fn log(&mut self, pop: &Population<P, F>) -> io::Result<()> {
+        let stats = pop.stats(self.sample_pairs);
+
+        if self.labels.is_none() {
+            let labels: Vec<Label> = stats.scores.iter().map(|&(ref name, _)| name.clone()).collect();
+
+            let mut header: Vec<String> = self.parameters.keys().cloned().collect();
+            header.extend(vec!["generation", "n", "unique_programs", "scoring_time", "variation_time"].into_iter().map(String::from));
+            header.extend(distribution_header("total_score"));
+            for name in labels.iter() {
+                header.extend(distribution_header(name));
+            }
+            header.extend(distribution_header("depth"));
+            header.extend(distribution_header("size"));
+            header.extend(vec!["mean_edit_distance", "distinct_scores", "score_entropy", "node_type_entropy"].into_iter().map(String::from));
+            header.push("champion_score".to_string());
+            header.push("champion_size".to_string());
+            write_row(&mut self.writer, &header)?;
+
+            self.labels = Some(labels);
+        }
+
+        let mut row: Vec<String> = self.parameters.values().cloned().collect();
+        row.push(stats.generation.to_string());
+        row.push(stats.n.to_string());
+        row.push(stats.unique_programs.to_string());
+        row.push(stats.scoring_time.to_string());
+        row.push(stats.variation_time.to_string());
+        row.extend(distribution_row(Some(&stats.total_score)));
+        for name in self.labels.as_ref().unwrap().iter() {
+            // Labels that don't occur in this generation are left empty
+            let distribution = stats.scores.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref d)| d);
+            row.extend(distribution_row(distribution));
+        }
+        row.extend(distribution_row(Some(&stats.depth)));
+        row.extend(distribution_row(Some(&stats.size)));
+        row.push(stats.diversity.mean_edit_distance.to_string());
+        row.push(stats.diversity.distinct_scores.to_string());
+        row.push(stats.diversity.score_entropy.to_string());
+        row.push(stats.diversity.node_type_entropy.to_string());
+
+        match pop.scores.iter().enumerate().max_by_key(|&(_, f)| f.score_card()) {
+            Some((i, fitness)) => {
+                row.push(fitness.score_card().total_score().to_string());
+                row.push(size(&pop.population[i]).to_string());
+            },
+            None => {
+                row.push(String::new());
+                row.push(String::new());
+            }
+        }
+        write_row(&mut self.writer, &row)?;
+        self.writer.flush()
+    }
+}
+
+fn distribution_header(name: &str) -> Vec<String> {
+    vec!["min", "median", "max", "mean", "std_dev"].into_iter()
+        .map(|stat| format!("{}_{}", name, stat))
+        .collect()
+}
+
+fn distribution_row(distribution: Option<&Distribution>) -> Vec<String> {
+    match distribution {
+        Some(d) => vec![d.min, d.median, d.max, d.mean, d.std_dev].into_iter().map(|x| x.to_string()).collect(),
+        None => vec![String::new(); 5]
+    }
+}
+
+fn write_row<W: Write>(writer: &mut W, row: &[String]) -> io::Result<()> {
+    let fields: Vec<String> = row.iter().map(|field| {
+        if field.contains(',') || field.contains('"') || field.contains('\n') {
+            format!("\"{}\"", field.replace("\"", "\"\""))
+        } else {
+            field.clone()
+        }
+    }).collect();
+    writeln!(writer, "{}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::genetic::SimpleFitness;
+
+    #[derive(Clone,PartialEq,Debug,RustcEncodable)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    fn population() -> Population<Tree, SimpleFitness> {
+        let mut pop = Population::new(2, 3);
+        pop.add(Tree::Leaf(1));
+        pop.add(Tree::Node(Box::new(Tree::Leaf(1)), Box::new(Tree::Leaf(2))));
+        pop.scores.push(SimpleFitness::new(vec![("food, eaten", 1.0)]));
+        pop.scores.push(SimpleFitness::new(vec![("food, eaten", 2.0)]));
+        pop
+    }
+
+    fn parameters() -> Parameters {
+        let mut parameters = Parameters::new();
+        parameters.insert("seed".to_string(), "7".to_string());
+        parameters
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut logger = JsonLinesLogger::new(vec![], parameters());
+        logger.log(&population()).unwrap();
+        logger.log(&population()).unwrap();
+
+        let output = String::from_utf8(logger.writer).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(2, lines.len());
+
+        let record = json::Json::from_str(lines[0]).unwrap();
+        assert_eq!(Some("7"), record.find_path(&["parameters", "seed"]).and_then(|j| j.as_string()));
+        assert_eq!(Some(3), record.find_path(&["champion", "generation"]).and_then(|j| j.as_u64()));
+        assert_eq!(Some(2.0), record.find_path(&["stats", "total_score", "max"]).and_then(|j| j.as_f64()));
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut logger = CsvLogger::new(vec![], parameters());
+        logger.log(&population()).unwrap();
+
+        let output = String::from_utf8(logger.writer).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("seed,generation,n,unique_programs,"));
+        assert!(lines[0].contains(",\"food, eaten_min\","));
+        assert!(lines[1].starts_with("7,3,2,2,"));
+        assert!(lines[1].ends_with(",2,3"));
+    }
+}
@@ -0,0 +1,62 @@
+//! Deterministic random number generation
+//!
+//! Every random decision in this library is taken using the random number
+//! generator that is passed in, so a run that starts from a generator created
+//! by `seeded_rng` can be replayed exactly by using the same seed again.
+use rand::{SeedableRng, StdRng};
+
+/// Create a random number generator from a single seed.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::from_seed(&split_seed(seed)[..])
+}
+
+/// Derive an independent random number generator for a single evaluation.
+///
+/// The generator only depends on the master seed, the generation and the
+/// index of the individual in the population, so it doesn't matter in which
+/// order (or on which thread) the individuals are evaluated.
+pub fn derive_rng(master_seed: u64, generation: u32, index: usize) -> StdRng {
+    let seed = split_seed(master_seed);
+    StdRng::from_seed(&[seed[0], seed[1], generation as usize, index][..])
+}
+
+/// Split a 64-bit seed into words that fit a `usize` on every platform.
+fn split_seed(seed: u64) -> [usize; 2] {
+    [(seed & 0xffffffff) as usize, (seed >> 32) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use super::super::Population;
+    use super::super::genetic::{Fitness, SimpleFitness};
+
+    #[test]
+    fn test_derived_rngs() {
+        let one = derive_rng(42, 1, 3).next_u64();
+        assert_eq!(one, derive_rng(42, 1, 3).next_u64());
+        assert!(one != derive_rng(42, 1, 4).next_u64());
+        assert!(one != derive_rng(42, 2, 3).next_u64());
+        assert!(one != derive_rng(43, 1, 3).next_u64());
+    }
+
+    #[test]
+    fn test_scoring_is_reproducible() {
+        fn random_score(_: &u32, rng: &mut Rng) -> SimpleFitness {
+            SimpleFitness::new(vec![("random", rng.next_f32())])
+        }
+
+        let score_with_seed = |seed| {
+            let mut pop : Population<u32, SimpleFitness> = Population::new(10, 0);
+            for i in 0..10 {
+                pop.add(i);
+            }
+            pop.score(random_score, &mut seeded_rng(seed));
+            pop.scores.iter().map(|f| f.score_card().total_score()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(score_with_seed(1), score_with_seed(1));
+        assert!(score_with_seed(1) != score_with_seed(2));
+    }
+}
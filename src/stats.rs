@@ -0,0 +1,150 @@
+use super::{AstNode, Number, Population, Fitness, Diversity};
+use super::ast::{depth, size, structural_hash};
+use super::genetic::Label;
+use super::num::{mean, median, std_dev, partial_min, partial_max};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Summary of a list of values.
+#[derive(Clone,Copy,Debug,Default,PartialEq,RustcEncodable)]
+pub struct Distribution {
+    pub min: Number,
+    pub median: Number,
+    pub max: Number,
+    pub mean: Number,
+    pub std_dev: Number
+}
+
+impl Distribution {
+    /// Summarize a list of values. All fields are 0 if the list is empty.
+    pub fn of(xs: &[Number]) -> Distribution {
+        Distribution {
+            min: partial_min(xs.iter().cloned()).unwrap_or(0.0),
+            median: median(xs).unwrap_or(0.0),
+            max: partial_max(xs.iter().cloned()).unwrap_or(0.0),
+            mean: mean(xs).unwrap_or(0.0),
+            std_dev: std_dev(xs).unwrap_or(0.0)
+        }
+    }
+}
+
+/// Statistics of a single generation of a population.
+///
+/// Get these from a scored population using `Population::stats()`.
+#[derive(Clone,Debug,RustcEncodable)]
+pub struct GenerationStats {
+    pub generation: u32,
+
+    /// Number of programs in the population
+    pub n: usize,
+
+    /// Number of structurally different programs in the population
+    pub unique_programs: usize,
+
+    /// Distribution of the total scores
+    pub total_score: Distribution,
+
+    /// Distribution of every labeled subscore, over the programs that have it
+    pub scores: Vec<(Label, Distribution)>,
+
+    /// Distribution of program depths
+    pub depth: Distribution,
+
+    /// Distribution of program sizes, in nodes
+    pub size: Distribution,
+
+    /// How different the programs in the population are
+    pub diversity: Diversity,
+
+    /// Seconds spent on scoring this generation
+    pub scoring_time: f64,
+
+    /// Seconds spent on producing this generation from the previous one
+    pub variation_time: f64
+}
+
+impl GenerationStats {
+    /// Measure a scored population.
+    ///
+    /// The average edit distance between programs is estimated from
+    /// `sample_pairs` pairs of programs, see `Diversity::of`. Programs that
+    /// couldn't be evaluated are left out of the score statistics.
+    pub fn of<P, F>(pop: &Population<P, F>, sample_pairs: usize) -> GenerationStats
+        where P: AstNode+Clone+Sync,
+              F: Fitness+Sized+Send
+    {
+        // Programs that couldn't be evaluated don't have meaningful scores
+        let evaluated = pop.scores.iter().map(|f| f.score_card()).filter(|c| !c.is_failed()).collect::<Vec<_>>();
+        let totals: Vec<Number> = evaluated.iter().map(|c| c.total_score()).collect();
+
+        let mut by_label: Vec<(Label, Vec<Number>)> = vec![];
+        for card in evaluated.iter() {
+            for &(ref name, x) in card.scores().iter() {
+                match by_label.iter().position(|&(ref n, _)| n == name) {
+                    Some(i) => by_label[i].1.push(x),
+                    None => by_label.push((name.clone(), vec![x]))
+                }
+            }
+        }
+
+        let depths: Vec<Number> = pop.population.iter().map(|p| depth(p) as Number).collect();
+        let sizes: Vec<Number> = pop.population.iter().map(|p| size(p) as Number).collect();
+        let unique: HashSet<u64> = pop.population.iter().map(|p| structural_hash(p)).collect();
+
+        GenerationStats {
+            generation: pop.generation,
+            n: pop.n(),
+            unique_programs: unique.len(),
+            total_score: Distribution::of(&totals),
+            scores: by_label.into_iter().map(|(name, xs)| (name, Distribution::of(&xs))).collect(),
+            depth: Distribution::of(&depths),
+            size: Distribution::of(&sizes),
+            diversity: Diversity::of(pop, sample_pairs),
+            scoring_time: seconds(pop.scoring_time),
+            variation_time: seconds(pop.variation_time)
+        }
+    }
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::genetic::SimpleFitness;
+
+    #[derive(Clone,PartialEq,Debug)]
+    enum Tree {
+        Leaf(u32),
+        Node(Box<Tree>, Box<Tree>)
+    }
+
+    impl_astnode!(Tree, 0,
+                  leaf Leaf((data value |rng: &mut ::rand::Rng| rng.next_u32() % 10)),
+                  int Node(left, right));
+
+    #[test]
+    fn test_stats() {
+        let mut pop: Population<Tree, SimpleFitness> = Population::new(3, 0);
+        pop.add(Tree::Leaf(1));
+        pop.add(Tree::Leaf(1));
+        pop.add(Tree::Node(Box::new(Tree::Leaf(1)), Box::new(Tree::Leaf(2))));
+        pop.scores.push(SimpleFitness::new(vec![("a", 1.0)]));
+        pop.scores.push(SimpleFitness::new(vec![("a", 2.0)]));
+        pop.scores.push(SimpleFitness::new(vec![("a", 3.0), ("b", 1.0)]));
+
+        let stats = pop.stats(10);
+
+        assert_eq!(2, stats.unique_programs);
+        let total = stats.total_score;
+        assert_eq!((1.0, 2.0, 4.0, 7.0 / 3.0), (total.min, total.median, total.max, total.mean));
+        assert!((total.std_dev - (14.0 as Number / 9.0).sqrt()).abs() < 1e-5);
+        assert_eq!(2, stats.scores.len());
+        assert_eq!(2.0, stats.scores[0].1.median);
+        assert_eq!(1.0, stats.scores[1].1.max);
+        assert_eq!(3.0, stats.size.max);
+        assert_eq!(2.0 / 3.0, stats.diversity.unique_fraction);
+    }
+}